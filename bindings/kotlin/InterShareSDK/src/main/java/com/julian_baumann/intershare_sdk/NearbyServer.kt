@@ -19,6 +19,10 @@ class NearbyServer(context: Context, myDevice: Device, delegate: NearbyConnectio
         context.getSystemService(Context.BLUETOOTH_SERVICE) as BluetoothManager
     }
 
+    init {
+        setConfigDir(context.filesDir.absolutePath)
+    }
+
     private val internal: InternalNearbyServer = InternalNearbyServer(
         myDevice,
         Environment.getExternalStoragePublicDirectory(Environment.DIRECTORY_DOWNLOADS).absolutePath,
@@ -109,16 +113,32 @@ class NearbyServer(context: Context, myDevice: Device, delegate: NearbyConnectio
         internal.changeDevice(newDevice)
     }
 
-    suspend fun shareFiles(urls: List<String>, allowConvenienceDownload: Boolean, progressDelegate: ShareProgressDelegate?): ShareStore {
-        return internal.shareFiles(urls, allowConvenienceDownload, progressDelegate)
+    suspend fun shareFiles(urls: List<String>, allowConvenienceDownload: Boolean, accessCode: String? = null): ShareStore {
+        return internal.shareFiles(urls, allowConvenienceDownload, accessCode)
+    }
+
+    suspend fun shareText(text: String, allowConvenienceDownload: Boolean, accessCode: String? = null): ShareStore {
+        return internal.shareText(text, allowConvenienceDownload, accessCode)
+    }
+
+    suspend fun requestDownload(link: String, accessCode: String? = null): ConnectionRequest {
+        return internal.requestDownload(link, accessCode)
+    }
+
+    suspend fun startHttpServer(): UShort {
+        return internal.startHttpServer()
+    }
+
+    suspend fun stopHttpServer() {
+        internal.stopHttpServer()
     }
 
-    suspend fun shareText(text: String, allowConvenienceDownload: Boolean): ShareStore {
-        return internal.shareText(text, allowConvenienceDownload)
+    suspend fun getShareStores(): List<ShareStore> {
+        return internal.getShareStores()
     }
 
-    suspend fun requestDownload(link: String) {
-        return internal.requestDownload(link)
+    suspend fun revokeShareStore(requestId: String): Boolean {
+        return internal.revokeShareStore(requestId)
     }
 
     suspend fun stop() {
@@ -109,18 +109,36 @@ public class NearbyServer {
 
     @available(macOS 13.0, *)
     @available(iOS 14.0, *)
-    public func share(urls: [String], allowConvenienceShare: Bool = true) async -> ShareStore {
-        return await internalHandler.shareFiles(filePaths: urls, allowConvenienceShare: allowConvenienceShare)
+    public func share(urls: [String], allowConvenienceShare: Bool = true, accessCode: String? = nil) async -> ShareStore {
+        return await internalHandler.shareFiles(filePaths: urls, allowConvenienceShare: allowConvenienceShare, accessCode: accessCode)
     }
 
     @available(macOS 13.0, *)
     @available(iOS 14.0, *)
-    public func share(text: String, allowConvenienceShare: Bool = true) async -> ShareStore {
-        return await internalHandler.shareText(text: text, allowConvenienceShare: allowConvenienceShare)
+    public func share(text: String, allowConvenienceShare: Bool = true, accessCode: String? = nil) async -> ShareStore {
+        return await internalHandler.shareText(text: text, allowConvenienceShare: allowConvenienceShare, accessCode: accessCode)
     }
     
-    public func requestDownload(link: String) async throws {
-        try await internalHandler.requestDownload(link: link)
+    public func requestDownload(link: String, accessCode: String? = nil) async throws -> ConnectionRequest {
+        return try await internalHandler.requestDownload(link: link, accessCode: accessCode)
+    }
+
+    @discardableResult
+    public func startHttpServer() async throws -> UInt16 {
+        return try await internalHandler.startHttpServer()
+    }
+
+    public func stopHttpServer() async {
+        await internalHandler.stopHttpServer()
+    }
+
+    public func getShareStores() async -> [ShareStore] {
+        return await internalHandler.getShareStores()
+    }
+
+    @discardableResult
+    public func revokeShareStore(requestId: String) async -> Bool {
+        return await internalHandler.revokeShareStore(requestId: requestId)
     }
 
     public func stop() async throws {
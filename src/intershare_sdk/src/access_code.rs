@@ -0,0 +1,130 @@
+use crate::communication::Session;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+/// Wrong access codes accepted per share, before it is locked for convenience downloads.
+pub const MAX_ACCESS_CODE_ATTEMPTS: u32 = 5;
+
+/// How long a share stays locked after `MAX_ACCESS_CODE_ATTEMPTS` wrong codes.
+/// Doubles with every further lockout, up to `MAX_ACCESS_CODE_LOCKOUT`.
+pub const ACCESS_CODE_LOCKOUT: Duration = Duration::from_secs(30);
+pub const MAX_ACCESS_CODE_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+const SHARER_CONFIRMATION_LABEL: &[u8] = b"intershare access code sharer confirmation";
+const DOWNLOADER_CONFIRMATION_LABEL: &[u8] = b"intershare access code downloader confirmation";
+
+/// SPAKE2 exchange proving that both peers know the same access code without revealing it.
+/// The handshake transcript is used as identity, so an exchange can't be relayed into another session.
+pub struct AccessCodeExchange {
+    spake: Spake2<Ed25519Group>,
+    transcript_hash: [u8; 32],
+}
+
+/// Key confirmation values both peers derive from the shared PAKE key.
+pub struct AccessCodeConfirmations {
+    pub sharer: [u8; 32],
+    pub downloader: [u8; 32],
+}
+
+impl AccessCodeExchange {
+    /// Starts the exchange and returns the message to send to the peer.
+    pub fn start(access_code: &str, session: &Session) -> (Self, Vec<u8>) {
+        let (spake, message) = Spake2::<Ed25519Group>::start_symmetric(
+            &Password::new(access_code.trim().as_bytes()),
+            &Identity::new(&session.transcript_hash),
+        );
+
+        return (
+            Self {
+                spake,
+                transcript_hash: session.transcript_hash,
+            },
+            message,
+        );
+    }
+
+    /// Returns `None` if the foreign message is malformed.
+    /// A wrong access code only shows up as mismatching confirmations.
+    pub fn finish(self, foreign_message: &[u8]) -> Option<AccessCodeConfirmations> {
+        let key = self.spake.finish(foreign_message).ok()?;
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.transcript_hash), &key);
+
+        let mut confirmations = AccessCodeConfirmations {
+            sharer: [0u8; 32],
+            downloader: [0u8; 32],
+        };
+
+        hkdf.expand(SHARER_CONFIRMATION_LABEL, &mut confirmations.sharer)
+            .expect("32 bytes is a valid HKDF output length");
+        hkdf.expand(DOWNLOADER_CONFIRMATION_LABEL, &mut confirmations.downloader)
+            .expect("32 bytes is a valid HKDF output length");
+
+        return Some(confirmations);
+    }
+}
+
+pub fn confirmation_matches(expected: &[u8; 32], received: &[u8]) -> bool {
+    return expected.as_slice().ct_eq(received).into();
+}
+
+/// Limits wrong access code guesses per share with an exponentially growing lockout.
+/// Only exchanges the downloader completed with a wrong confirmation count as failed.
+#[derive(Default, Debug)]
+pub struct AccessCodeAttempts {
+    failed: u32,
+    pending: u32,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl AccessCodeAttempts {
+    /// Reserves an attempt, so concurrent exchanges can't exceed the limit.
+    /// Returns `false` while the share is locked. Every reserved attempt has to be
+    /// released with `succeeded`, `failed` or `abandoned`.
+    pub fn reserve(&mut self, now: Instant) -> bool {
+        if self.locked_until.is_some_and(|locked_until| now < locked_until) {
+            return false;
+        }
+
+        if self.failed + self.pending >= MAX_ACCESS_CODE_ATTEMPTS {
+            return false;
+        }
+
+        self.pending += 1;
+        return true;
+    }
+
+    pub fn succeeded(&mut self) {
+        self.pending = self.pending.saturating_sub(1);
+        self.failed = 0;
+        self.lockouts = 0;
+        self.locked_until = None;
+    }
+
+    pub fn failed(&mut self, now: Instant) {
+        self.pending = self.pending.saturating_sub(1);
+        self.failed += 1;
+
+        if self.failed < MAX_ACCESS_CODE_ATTEMPTS {
+            return;
+        }
+
+        let lockout = ACCESS_CODE_LOCKOUT
+            .checked_mul(1 << self.lockouts.min(16))
+            .map_or(MAX_ACCESS_CODE_LOCKOUT, |lockout| {
+                lockout.min(MAX_ACCESS_CODE_LOCKOUT)
+            });
+
+        self.failed = 0;
+        self.lockouts += 1;
+        self.locked_until = Some(now + lockout);
+    }
+
+    /// The downloader left before confirming its code, so it learned nothing about it.
+    pub fn abandoned(&mut self) {
+        self.pending = self.pending.saturating_sub(1);
+    }
+}
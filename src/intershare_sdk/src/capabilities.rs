@@ -0,0 +1,87 @@
+use crate::errors::IncomingErrors;
+use crate::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use protocol::communication::capabilities::{Cipher, Compression, IntentType};
+use protocol::communication::Capabilities;
+use protocol::prost::Message;
+
+/// Feature set both peers agreed on during the handshake.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NegotiatedCapabilities {
+    pub protocol_version: u32,
+    pub cipher: Cipher,
+    pub compression: Compression,
+    pub resume: bool,
+    pub intents: Vec<IntentType>,
+}
+
+impl NegotiatedCapabilities {
+    pub fn supports_intent(&self, intent: IntentType) -> bool {
+        return self.intents.contains(&intent);
+    }
+}
+
+/// Everything this version supports, ordered by preference.
+pub fn local_capabilities() -> Capabilities {
+    return Capabilities {
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        max_protocol_version: PROTOCOL_VERSION,
+        ciphers: vec![Cipher::Xchacha20Poly1305Stream as i32],
+        compression: vec![Compression::Zstd as i32, Compression::None as i32],
+        resume: true,
+        intents: vec![
+            IntentType::FileTransfer as i32,
+            IntentType::Clipboard as i32,
+            IntentType::RichClipboard as i32,
+        ],
+    };
+}
+
+pub fn encode_capabilities(capabilities: &Capabilities) -> Vec<u8> {
+    return capabilities.encode_to_vec();
+}
+
+pub fn decode_capabilities(bytes: &[u8]) -> Result<Capabilities, IncomingErrors> {
+    return Capabilities::decode(bytes).map_err(|_| IncomingErrors::InvalidCapabilities);
+}
+
+/// Picks the best common feature set. Both peers pass the sender's capabilities first,
+/// so the sender's preference order decides and both sides end up with the same result.
+pub fn negotiate(
+    sender: &Capabilities,
+    receiver: &Capabilities,
+) -> Result<NegotiatedCapabilities, IncomingErrors> {
+    let protocol_version = sender
+        .max_protocol_version
+        .min(receiver.max_protocol_version);
+
+    if protocol_version
+        < sender
+            .min_protocol_version
+            .max(receiver.min_protocol_version)
+    {
+        return Err(IncomingErrors::IncompatibleCapabilities);
+    }
+
+    let cipher = sender
+        .ciphers()
+        .find(|cipher| receiver.ciphers().any(|other| other == *cipher))
+        .ok_or(IncomingErrors::IncompatibleCapabilities)?;
+
+    let compression = sender
+        .compression()
+        .find(|compression| receiver.compression().any(|other| other == *compression))
+        .unwrap_or(Compression::None);
+
+    let intents = sender
+        .intents()
+        .filter(|intent| receiver.intents().any(|other| other == *intent))
+        .collect();
+
+    return Ok(NegotiatedCapabilities {
+        protocol_version,
+        cipher,
+        compression,
+        resume: sender.resume && receiver.resume,
+        intents,
+    });
+}
@@ -0,0 +1,164 @@
+use crate::manifest::SharedData;
+use protocol::communication::ClipboardRepresentation;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+const PLAIN_TEXT_MIME_TYPE: &str = "text/plain";
+const CLIPBOARD_FILE_NAME: &str = "clipboard";
+
+/// Upper bound for what a receiver keeps in memory for a single clipboard share.
+/// Larger clipboard content is shared as files, which the receiver writes to disk.
+pub const MAX_CLIPBOARD_SIZE: u64 = 16 * 1024 * 1024;
+
+/// One representation of the clipboard content, e.g. `text/html`, `image/png` or `text/uri-list`.
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct ClipboardItem {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+fn is_plain_text(mime_type: &str) -> bool {
+    return mime_type.split(';').next().map(str::trim) == Some(PLAIN_TEXT_MIME_TYPE);
+}
+
+pub(crate) fn plain_text_item(text: String) -> ClipboardItem {
+    return ClipboardItem {
+        mime_type: PLAIN_TEXT_MIME_TYPE.to_string(),
+        data: text.into_bytes(),
+    };
+}
+
+/// Takes the plain text out of the items. It travels inside the request like a text share,
+/// so receivers without support for other representations still get it.
+pub(crate) fn split_plain_text(items: Vec<ClipboardItem>) -> (Option<String>, Vec<ClipboardItem>) {
+    let mut text = None;
+    let mut representations = Vec::new();
+
+    for item in items {
+        if text.is_some() || !is_plain_text(&item.mime_type) {
+            representations.push(item);
+            continue;
+        }
+
+        match String::from_utf8(item.data) {
+            Ok(plain_text) => text = Some(plain_text),
+            Err(error) => representations.push(ClipboardItem {
+                mime_type: item.mime_type,
+                data: error.into_bytes(),
+            }),
+        }
+    }
+
+    return (text, representations);
+}
+
+fn file_extension(mime_type: &str) -> &'static str {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+
+    return match essence {
+        PLAIN_TEXT_MIME_TYPE => "txt",
+        "text/html" => "html",
+        "text/uri-list" => "uri",
+        "image/jpeg" => "jpg",
+        _ => mime_guess::get_mime_extensions_str(essence)
+            .and_then(|extensions| extensions.first().copied())
+            .unwrap_or("bin"),
+    };
+}
+
+/// Turns the items into files named after their MIME type, e.g. `clipboard.html`.
+pub(crate) fn clipboard_files(items: Vec<ClipboardItem>) -> Vec<SharedData> {
+    let mut names = HashSet::new();
+
+    return items
+        .into_iter()
+        .map(|item| {
+            let extension = file_extension(&item.mime_type);
+            let mut name = format!("{CLIPBOARD_FILE_NAME}.{extension}");
+            let mut counter = 1;
+
+            while !names.insert(name.clone()) {
+                counter += 1;
+                name = format!("{CLIPBOARD_FILE_NAME} ({counter}).{extension}");
+            }
+
+            return SharedData {
+                name,
+                data: item.data,
+            };
+        })
+        .collect();
+}
+
+pub(crate) fn representations(items: &[ClipboardItem]) -> Vec<ClipboardRepresentation> {
+    return items
+        .iter()
+        .map(|item| ClipboardRepresentation {
+            mime_type: item.mime_type.clone(),
+            size: item.data.len() as u64,
+        })
+        .collect();
+}
+
+pub fn total_size(representations: &[ClipboardRepresentation]) -> u64 {
+    return representations.iter().fold(0u64, |total, representation| {
+        total.saturating_add(representation.size)
+    });
+}
+
+/// What the receiver's acknowledgement of a clipboard share is signed over.
+pub(crate) fn clipboard_digest(text: &str, items: &[ClipboardItem]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update((text.len() as u64).to_be_bytes());
+    hasher.update(text.as_bytes());
+
+    for item in items {
+        hasher.update((item.mime_type.len() as u64).to_be_bytes());
+        hasher.update(item.mime_type.as_bytes());
+        hasher.update((item.data.len() as u64).to_be_bytes());
+        hasher.update(&item.data);
+    }
+
+    return hasher.finalize().to_vec();
+}
+
+/// Streams the data of each item, in the order they were announced in the request.
+pub(crate) fn write_items<W: Write + ?Sized>(
+    stream: &mut W,
+    items: &[ClipboardItem],
+) -> io::Result<()> {
+    for item in items {
+        stream.write_all(&item.data)?;
+    }
+
+    return stream.flush();
+}
+
+pub(crate) fn read_items<R: Read + ?Sized>(
+    stream: &mut R,
+    representations: &[ClipboardRepresentation],
+) -> io::Result<Vec<ClipboardItem>> {
+    let mut items = Vec::with_capacity(representations.len());
+
+    for representation in representations {
+        let mut data = Vec::new();
+        (&mut *stream)
+            .take(representation.size)
+            .read_to_end(&mut data)?;
+
+        if data.len() as u64 != representation.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Clipboard data ended early",
+            ));
+        }
+
+        items.push(ClipboardItem {
+            mime_type: representation.mime_type.clone(),
+            data,
+        });
+    }
+
+    return Ok(items);
+}
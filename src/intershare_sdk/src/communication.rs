@@ -1,27 +1,120 @@
-use crate::encryption::generate_iv;
-use crate::encryption::EncryptedStream;
+use crate::capabilities::{
+    decode_capabilities, encode_capabilities, local_capabilities, negotiate, NegotiatedCapabilities,
+};
+use crate::encryption::{derive_session_keys, generate_iv, HandshakeRole};
+use crate::encryption::{EncryptedStream, NONCE_PREFIX_LENGTH};
+use crate::errors::IncomingErrors;
+use crate::identity::{verify_signature, DeviceIdentity};
 use log::info;
 use prost_stream::Stream;
-use protocol::communication::{EncryptionRequest, EncryptionResponse};
+use protocol::communication::{EncryptionConfirmation, EncryptionRequest, EncryptionResponse};
 use rand_core::OsRng;
+use sha2::{Digest, Sha256};
 use std::error::Error;
 use std::io::{Read, Write};
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
+const TRANSCRIPT_LABEL: &[u8] = b"intershare handshake v1";
+const COMMITMENT_LABEL: &[u8] = b"intershare public key commitment";
+const SAS_LABEL: &[u8] = b"intershare short authentication string";
+const SENDER_SIGNATURE_LABEL: &[u8] = b"intershare sender identity";
+const RECEIVER_SIGNATURE_LABEL: &[u8] = b"intershare receiver identity";
+
+/// Keys and capabilities exchanged in plaintext during the handshake.
+pub struct HandshakeTranscript<'a> {
+    pub sender_public_key: &'a [u8; 32],
+    pub receiver_public_key: &'a [u8; 32],
+    pub iv: &'a [u8; NONCE_PREFIX_LENGTH],
+    pub sender_identity_key: &'a [u8; 32],
+    pub receiver_identity_key: &'a [u8; 32],
+    pub sender_capabilities: &'a [u8],
+    pub receiver_capabilities: &'a [u8],
+}
+
+/// Outcome of a completed handshake, shared by both peers.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub transcript_hash: [u8; 32],
+    pub peer_identity_key: [u8; 32],
+    pub capabilities: NegotiatedCapabilities,
+}
+
+impl Session {
+    /// Six digit code derived from the transcript. Both peers only show the same code,
+    /// if nobody tampered with the key exchange.
+    pub fn short_authentication_string(&self) -> String {
+        let digest: [u8; 32] = Sha256::new()
+            .chain_update(SAS_LABEL)
+            .chain_update(self.transcript_hash)
+            .finalize()
+            .into();
+
+        let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+
+        return format!("{:06}", value % 1_000_000);
+    }
+}
+
+pub fn transcript_hash(transcript: &HandshakeTranscript) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(TRANSCRIPT_LABEL);
+    hasher.update(transcript.sender_public_key);
+    hasher.update(transcript.receiver_public_key);
+    hasher.update(transcript.iv);
+    hasher.update(transcript.sender_identity_key);
+    hasher.update(transcript.receiver_identity_key);
+
+    // Capabilities are variable length, so they are length prefixed to keep the encoding unambiguous.
+    for capabilities in [
+        transcript.sender_capabilities,
+        transcript.receiver_capabilities,
+    ] {
+        hasher.update((capabilities.len() as u64).to_be_bytes());
+        hasher.update(capabilities);
+    }
+
+    return hasher.finalize().into();
+}
+
+/// The sender commits to its ephemeral key before seeing the receiver's key, so a
+/// man in the middle can't grind keys until both short authentication strings match.
+pub fn public_key_commitment(public_key: &[u8; 32]) -> [u8; 32] {
+    return Sha256::new()
+        .chain_update(COMMITMENT_LABEL)
+        .chain_update(public_key)
+        .finalize()
+        .into();
+}
+
+fn signature_message(label: &[u8], transcript_hash: &[u8; 32]) -> Vec<u8> {
+    return [label, transcript_hash.as_slice()].concat();
+}
+
+fn to_key(bytes: Vec<u8>, error: IncomingErrors) -> Result<[u8; 32], IncomingErrors> {
+    return bytes.try_into().map_err(|_| error);
+}
+
 pub async fn initiate_sender_communication<T>(
     mut stream: T,
-) -> Result<EncryptedStream<T>, Box<dyn Error>>
+    identity: &DeviceIdentity,
+) -> Result<(EncryptedStream<T>, Session), Box<dyn Error>>
 where
     T: Read + Write,
 {
     info!("[Encryption] Initiating sender encryption communication");
     let secret = EphemeralSecret::random_from_rng(OsRng);
     let public_key = PublicKey::from(&secret);
+    let identity_key = identity.public_key();
+    let capabilities = local_capabilities();
+    let encoded_capabilities = encode_capabilities(&capabilities);
     let encryption_request = EncryptionRequest {
-        public_key: public_key.as_bytes().to_vec(),
+        public_key: Vec::new(),
+        identity_public_key: identity_key.to_vec(),
+        capabilities: encoded_capabilities.clone(),
+        public_key_commitment: public_key_commitment(public_key.as_bytes()).to_vec(),
     };
 
-    info!("[Encryption] Sending public key");
+    info!("[Encryption] Sending public key commitment");
     let mut prost_stream = Stream::new(&mut stream);
     let _ = prost_stream.send(&encryption_request);
 
@@ -32,33 +125,88 @@ where
 
     info!("[Encryption] Received foreign public key");
 
-    let public_key: [u8; 32] = encryption_response
-        .public_key
+    let foreign_public_key = to_key(
+        encryption_response.public_key,
+        IncomingErrors::InvalidForeignPublicKey,
+    )?;
+    let foreign_identity_key = to_key(
+        encryption_response.identity_public_key,
+        IncomingErrors::InvalidIdentityKey,
+    )?;
+
+    let iv: [u8; NONCE_PREFIX_LENGTH] = encryption_response
+        .nonce_prefix
         .try_into()
-        .expect("Vec length is not 32");
-    let foreign_public_key = PublicKey::from(public_key);
+        .map_err(|_| IncomingErrors::InvalidNonce)?;
 
-    info!("[Encryption] Doin the diffie hellman. Yeah.");
-    let shared_secret = secret.diffie_hellman(&foreign_public_key);
+    let negotiated_capabilities = negotiate(
+        &capabilities,
+        &decode_capabilities(&encryption_response.capabilities)?,
+    )?;
 
-    let iv: [u8; 24] = encryption_response
-        .iv
-        .try_into()
-        .expect("Vec length is not 24");
+    info!(
+        "[Encryption] Negotiated protocol version {}",
+        negotiated_capabilities.protocol_version
+    );
+
+    let transcript = transcript_hash(&HandshakeTranscript {
+        sender_public_key: public_key.as_bytes(),
+        receiver_public_key: &foreign_public_key,
+        iv: &iv,
+        sender_identity_key: &identity_key,
+        receiver_identity_key: &foreign_identity_key,
+        sender_capabilities: &encoded_capabilities,
+        receiver_capabilities: &encryption_response.capabilities,
+    });
+
+    info!("[Encryption] Revealing public key");
+    let _ = prost_stream.send(&EncryptionConfirmation {
+        identity_signature: identity
+            .sign(&signature_message(SENDER_SIGNATURE_LABEL, &transcript))
+            .to_vec(),
+        public_key: Some(public_key.as_bytes().to_vec()),
+    });
+
+    let encryption_confirmation = match prost_stream.recv::<EncryptionConfirmation>() {
+        Ok(message) => message,
+        Err(error) => return Err(Box::new(error)),
+    };
+
+    if !verify_signature(
+        &foreign_identity_key,
+        &signature_message(RECEIVER_SIGNATURE_LABEL, &transcript),
+        &encryption_confirmation.identity_signature,
+    ) {
+        return Err(Box::new(IncomingErrors::InvalidIdentitySignature));
+    }
+
+    info!("[Encryption] Doin the diffie hellman. Yeah.");
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(foreign_public_key));
+    let keys = derive_session_keys(shared_secret.as_bytes(), &transcript, HandshakeRole::Sender);
 
-    let encrypted_stream = EncryptedStream::new(shared_secret.to_bytes(), iv, stream);
+    let encrypted_stream = EncryptedStream::new(keys, stream);
 
-    return Ok(encrypted_stream);
+    return Ok((
+        encrypted_stream,
+        Session {
+            transcript_hash: transcript,
+            peer_identity_key: foreign_identity_key,
+            capabilities: negotiated_capabilities,
+        },
+    ));
 }
 
 pub fn initiate_receiver_communication<T>(
     mut stream: T,
-) -> Result<EncryptedStream<T>, Box<dyn Error>>
+    identity: &DeviceIdentity,
+) -> Result<(EncryptedStream<T>, Session), Box<dyn Error>>
 where
     T: Read + Write,
 {
     let secret = EphemeralSecret::random_from_rng(OsRng);
     let public_key = PublicKey::from(&secret);
+    let identity_key = identity.public_key();
+    let encoded_capabilities = encode_capabilities(&local_capabilities());
 
     let iv = generate_iv();
 
@@ -69,20 +217,86 @@ where
         Err(error) => return Err(Box::new(error)),
     };
 
+    // Version 0 senders can't parse the response, so they are turned away before it is sent.
+    if encryption_request.capabilities.is_empty() {
+        return Err(Box::new(IncomingErrors::LegacyProtocolVersion));
+    }
+
+    let foreign_identity_key = to_key(
+        encryption_request.identity_public_key,
+        IncomingErrors::InvalidIdentityKey,
+    )?;
+
     let _ = prost_stream.send(&EncryptionResponse {
         public_key: public_key.as_bytes().to_vec(),
-        iv: iv.to_vec(),
+        iv: Vec::new(),
+        identity_public_key: identity_key.to_vec(),
+        capabilities: encoded_capabilities.clone(),
+        nonce_prefix: iv.to_vec(),
     });
 
-    let public_key: [u8; 32] = encryption_request
-        .public_key
-        .try_into()
-        .expect("Vec length is not 32");
-    let foreign_public_key = PublicKey::from(public_key);
+    // The response is sent in any case, so an incompatible sender can report the reason itself.
+    let negotiated_capabilities = negotiate(
+        &decode_capabilities(&encryption_request.capabilities)?,
+        &local_capabilities(),
+    )?;
+
+    let encryption_confirmation = match prost_stream.recv::<EncryptionConfirmation>() {
+        Ok(message) => message,
+        Err(error) => return Err(Box::new(error)),
+    };
+
+    let foreign_public_key = to_key(
+        encryption_confirmation.public_key.unwrap_or_default(),
+        IncomingErrors::InvalidForeignPublicKey,
+    )?;
+
+    if public_key_commitment(&foreign_public_key).as_slice()
+        != encryption_request.public_key_commitment.as_slice()
+    {
+        return Err(Box::new(IncomingErrors::InvalidPublicKeyCommitment));
+    }
+
+    let transcript = transcript_hash(&HandshakeTranscript {
+        sender_public_key: &foreign_public_key,
+        receiver_public_key: public_key.as_bytes(),
+        iv: &iv,
+        sender_identity_key: &foreign_identity_key,
+        receiver_identity_key: &identity_key,
+        sender_capabilities: &encryption_request.capabilities,
+        receiver_capabilities: &encoded_capabilities,
+    });
+
+    if !verify_signature(
+        &foreign_identity_key,
+        &signature_message(SENDER_SIGNATURE_LABEL, &transcript),
+        &encryption_confirmation.identity_signature,
+    ) {
+        return Err(Box::new(IncomingErrors::InvalidIdentitySignature));
+    }
+
+    let _ = prost_stream.send(&EncryptionConfirmation {
+        identity_signature: identity
+            .sign(&signature_message(RECEIVER_SIGNATURE_LABEL, &transcript))
+            .to_vec(),
+        public_key: None,
+    });
 
-    let shared_secret = secret.diffie_hellman(&foreign_public_key);
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(foreign_public_key));
+    let keys = derive_session_keys(
+        shared_secret.as_bytes(),
+        &transcript,
+        HandshakeRole::Receiver,
+    );
 
-    let encrypted_stream = EncryptedStream::new(shared_secret.to_bytes(), iv, stream);
+    let encrypted_stream = EncryptedStream::new(keys, stream);
 
-    return Ok(encrypted_stream);
+    return Ok((
+        encrypted_stream,
+        Session {
+            transcript_hash: transcript,
+            peer_identity_key: foreign_identity_key,
+            capabilities: negotiated_capabilities,
+        },
+    ));
 }
@@ -0,0 +1,132 @@
+use crate::share_store::ConnectionMedium;
+use protocol::communication::capabilities::Compression;
+use protocol::communication::FileManifestEntry;
+use std::io::{self, BufReader, Read, Write};
+
+/// Formats that are compressed already. Compressing them again only costs CPU time.
+const COMPRESSED_MIME_TYPES: [&str; 14] = [
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/x-xz",
+    "application/x-bzip2",
+    "application/zstd",
+    "application/pdf",
+    "application/epub+zip",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+];
+
+/// Media formats that are stored uncompressed and still benefit from compression.
+const UNCOMPRESSED_MEDIA_TYPES: [&str; 5] = [
+    "image/bmp",
+    "image/svg+xml",
+    "image/x-ms-bmp",
+    "audio/wav",
+    "audio/x-wav",
+];
+
+pub fn is_compressible(mime_type: Option<&str>) -> bool {
+    let Some(mime_type) = mime_type else {
+        return true;
+    };
+
+    if UNCOMPRESSED_MEDIA_TYPES.contains(&mime_type) {
+        return true;
+    }
+
+    let is_media = ["image/", "video/", "audio/"]
+        .iter()
+        .any(|prefix| mime_type.starts_with(prefix));
+
+    return !is_media && !COMPRESSED_MIME_TYPES.contains(&mime_type);
+}
+
+/// Compression is only worth it if most of the transferred bytes are compressible.
+pub fn should_compress<'a>(entries: impl IntoIterator<Item = &'a FileManifestEntry>) -> bool {
+    let (compressible, total) =
+        entries
+            .into_iter()
+            .fold((0u64, 0u64), |(compressible, total), entry| {
+                if is_compressible(entry.mime_type.as_deref()) {
+                    (compressible + entry.size, total + entry.size)
+                } else {
+                    (compressible, total + entry.size)
+                }
+            });
+
+    return total > 0 && compressible * 2 >= total;
+}
+
+/// Bandwidth is scarce over BLE, so spending more CPU time pays off there.
+pub fn compression_level(medium: &ConnectionMedium) -> i32 {
+    return match medium {
+        ConnectionMedium::BLE => 9,
+        ConnectionMedium::WiFi => 1,
+    };
+}
+
+pub(crate) enum ArchiveWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(inner: W, compression_level: Option<i32>) -> io::Result<Self> {
+        return Ok(match compression_level {
+            Some(level) => ArchiveWriter::Zstd(zstd::Encoder::new(inner, level)?),
+            None => ArchiveWriter::Plain(inner),
+        });
+    }
+
+    /// Writes the end of the compressed frame and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        return match self {
+            ArchiveWriter::Plain(inner) => Ok(inner),
+            ArchiveWriter::Zstd(encoder) => encoder.finish(),
+        };
+    }
+}
+
+impl<W: Write> Write for ArchiveWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        return match self {
+            ArchiveWriter::Plain(inner) => inner.write(buffer),
+            ArchiveWriter::Zstd(encoder) => encoder.write(buffer),
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return match self {
+            ArchiveWriter::Plain(inner) => inner.flush(),
+            ArchiveWriter::Zstd(encoder) => encoder.flush(),
+        };
+    }
+}
+
+pub(crate) enum ArchiveReader<R: Read> {
+    Plain(R),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(inner: R, compression: Compression) -> io::Result<Self> {
+        return Ok(match compression {
+            Compression::Zstd => ArchiveReader::Zstd(zstd::Decoder::new(inner)?),
+            Compression::None => ArchiveReader::Plain(inner),
+        });
+    }
+}
+
+impl<R: Read> Read for ArchiveReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        return match self {
+            ArchiveReader::Plain(inner) => inner.read(buffer),
+            ArchiveReader::Zstd(decoder) => decoder.read(buffer),
+        };
+    }
+}
@@ -1,14 +1,18 @@
 use crate::discovery::get_connection_details;
 use crate::{
-    communication::initiate_sender_communication,
+    communication::{initiate_sender_communication, Session},
     encryption::{EncryptedReadWrite, EncryptedStream},
     errors::ConnectErrors,
+    identity::DeviceIdentity,
+    is_compatible,
     nearby_server::L2CapDelegate,
     share_store::{ConnectionMedium, SendProgressDelegate, SendProgressState},
     stream::NativeStreamDelegate,
     transmission::tcp::TcpClient,
+    trust_store::{trust_store, TrustLevel},
+    VersionCompatibility,
 };
-use log::{error, info};
+use log::{error, info, warn};
 use protocol::discovery::{Device, DeviceConnectionInfo};
 use std::{
     collections::HashMap,
@@ -46,6 +50,7 @@ pub async fn handle_incoming_l2cap_connection(
 
 pub struct Connection {
     ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>,
+    identity: Arc<DeviceIdentity>,
 }
 
 fn update_progress(
@@ -58,28 +63,58 @@ fn update_progress(
 }
 
 impl Connection {
-    pub fn new(ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>) -> Self {
-        return Self { ble_l2_cap_client };
+    pub fn new(
+        ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>,
+        identity: Arc<DeviceIdentity>,
+    ) -> Self {
+        return Self {
+            ble_l2_cap_client,
+            identity,
+        };
     }
 
-    async fn initiate_sender<T>(&self, raw_stream: T) -> Result<EncryptedStream<T>, ConnectErrors>
+    async fn initiate_sender<T>(
+        &self,
+        raw_stream: T,
+        receiver_id: Option<&str>,
+    ) -> Result<(EncryptedStream<T>, Session), ConnectErrors>
     where
         T: Read + Write,
     {
-        return Ok(match initiate_sender_communication(raw_stream).await {
-            Ok(stream) => stream,
-            Err(error) => {
-                return Err(ConnectErrors::FailedToEncryptStream {
-                    error: error.to_string(),
-                })
+        let (encrypted_stream, session) =
+            match initiate_sender_communication(raw_stream, &self.identity).await {
+                Ok(result) => result,
+                Err(error) => {
+                    return Err(ConnectErrors::FailedToEncryptStream {
+                        error: error.to_string(),
+                    })
+                }
+            };
+
+        if let Some(receiver_id) = receiver_id {
+            let trust_level = trust_store()
+                .write()
+                .unwrap()
+                .check_and_pin(receiver_id, &session.peer_identity_key);
+
+            // Nothing is sent to a device that could be impersonating the receiver.
+            if trust_level == TrustLevel::KeyChanged {
+                warn!(
+                    "Identity key of {} changed since the last connection",
+                    receiver_id
+                );
+
+                return Err(ConnectErrors::ReceiverKeyChanged);
             }
-        });
+        }
+
+        return Ok((encrypted_stream, session));
     }
 
     pub async fn connect_tcp(
         &self,
         connection_details: &DeviceConnectionInfo,
-    ) -> Result<Box<dyn EncryptedReadWrite>, ConnectErrors> {
+    ) -> Result<(Box<dyn EncryptedReadWrite>, Session), ConnectErrors> {
         let Some(tcp_connection_details) = &connection_details.tcp else {
             return Err(ConnectErrors::FailedToGetTcpDetails);
         };
@@ -106,23 +141,41 @@ impl Connection {
             }
         })?;
 
-        let encrypted_stream = self.initiate_sender(raw_stream).await?;
-        return Ok(Box::new(encrypted_stream));
+        let receiver_id = connection_details
+            .device
+            .as_ref()
+            .map(|device| device.id.as_str());
+
+        let (encrypted_stream, session) = self.initiate_sender(raw_stream, receiver_id).await?;
+        return Ok((Box::new(encrypted_stream), session));
     }
 
     pub async fn connect(
         &self,
         device: Device,
         progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
-    ) -> Result<Box<dyn EncryptedReadWrite>, ConnectErrors> {
+    ) -> Result<(Box<dyn EncryptedReadWrite>, Session, ConnectionMedium), ConnectErrors> {
         L2CAP_CONNECTIONS.get_or_init(|| RwLock::new(HashMap::new()));
 
+        // Version 0 receivers would fail on the handshake without a reason.
+        if device.protocol_version.is_some()
+            && is_compatible(device.clone()) != VersionCompatibility::Compatible
+        {
+            return Err(ConnectErrors::InvalidProtocolVersion);
+        }
+
+        let receiver_id = device.id.clone();
         let connection_details =
             get_connection_details(device).ok_or(ConnectErrors::FailedToGetConnectionDetails)?;
 
         let encrypted_stream = self.connect_tcp(&connection_details).await;
 
-        if let Ok(encrypted_stream) = encrypted_stream {
+        // Other mediums lead to the same device
+        if matches!(encrypted_stream, Err(ConnectErrors::ReceiverKeyChanged)) {
+            return Err(ConnectErrors::ReceiverKeyChanged);
+        }
+
+        if let Ok((encrypted_stream, session)) = encrypted_stream {
             update_progress(
                 progress_delegate,
                 SendProgressState::ConnectionMediumUpdate {
@@ -130,7 +183,7 @@ impl Connection {
                 },
             );
 
-            return Ok(encrypted_stream);
+            return Ok((encrypted_stream, session, ConnectionMedium::WiFi));
         }
 
         info!("Could not connect via WiFi");
@@ -173,7 +226,9 @@ impl Connection {
 
         info!("Opened a L2CAP connection");
 
-        let encrypted_stream = self.initiate_sender(connection).await?;
+        let (encrypted_stream, session) = self
+            .initiate_sender(connection, Some(receiver_id.as_str()))
+            .await?;
 
         update_progress(
             progress_delegate,
@@ -182,6 +237,6 @@ impl Connection {
             },
         );
 
-        return Ok(Box::new(encrypted_stream));
+        return Ok((Box::new(encrypted_stream), session, ConnectionMedium::BLE));
     }
 }
@@ -1,14 +1,37 @@
-use crate::tar::untar_stream;
+use crate::clipboard::{
+    clipboard_digest, plain_text_item, read_items, total_size, ClipboardItem, MAX_CLIPBOARD_SIZE,
+};
+use crate::communication::Session;
+use crate::compression::should_compress;
+use crate::errors::{
+    DeclaredSizeExceeded, MissingDigests, ReceiveErrors, UndeclaredEntry, UnsafeArchiveError,
+};
+use crate::identity::DeviceIdentity;
+use crate::integrity::sign_acknowledgement;
+use crate::layout::{destination_dir, ReceivePolicy};
+use crate::limits::{available_space, ReceiveLimits, SpaceCheck};
+use crate::manifest::{selected_entries, ManifestEntry};
+use crate::resume::{remaining_size, resume_id, TransferJournal};
+use crate::staging::Staging;
+use crate::stream::NativeWriteDelegate;
+use crate::tar::{unpack_transfer, DeclaredSizes, ExtractTarget};
+use crate::trust_store::{trust_store, TrustLevel};
 use crate::{encryption::EncryptedReadWrite, nearby_server::ConnectionIntentType};
-use log::error;
+use log::{error, info, warn};
 use prost_stream::Stream;
+use protocol::communication;
+use protocol::communication::capabilities::Compression;
 use protocol::communication::request::Intent;
 use protocol::communication::{
-    ClipboardTransferIntent, FileTransferIntent, Request, TransferRequestResponse,
+    ClipboardTransferIntent, FileManifestEntry, Request, TransferCancellation,
+    TransferRequestResponse,
 };
 use protocol::discovery::Device;
+use protocol::prost::Message;
 use regex::Regex;
 use std::fmt::Debug;
+use std::io;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 use tokio::sync::RwLock;
@@ -17,9 +40,24 @@ use tokio::sync::RwLock;
 pub enum ReceiveProgressState {
     Unknown,
     Handshake,
-    Receiving { progress: f64 },
+    Receiving {
+        progress: f64,
+    },
     Extracting,
-    Cancelled,
+    /// `reason` is only set if the sender cancelled the transfer and gave one.
+    Cancelled {
+        reason: Option<String>,
+    },
+    /// The destination has less free space than the transfer needs, see `SpaceCheck::Warn`.
+    LowDiskSpace {
+        required: u64,
+        available: u64,
+    },
+    /// The listed files did not match the digest sent by the sender and were deleted.
+    /// The other files were moved into place.
+    IntegrityCheckFailed {
+        file_paths: Vec<String>,
+    },
     Finished,
 }
 
@@ -28,14 +66,42 @@ pub trait ReceiveProgressDelegate: Send + Sync + Debug {
     fn progress_changed(&self, progress: ReceiveProgressState);
 }
 
+/// A file transfer request as seen by the host app. The announced files are listed by `get_manifest`.
+#[derive(Clone, Debug)]
+pub struct FileTransferIntent {
+    pub file_name: Option<String>,
+    pub file_size: u64,
+    pub file_count: u64,
+    pub transfer_id: Option<String>,
+    pub compression: Vec<i32>,
+}
+
+impl From<communication::FileTransferIntent> for FileTransferIntent {
+    fn from(intent: communication::FileTransferIntent) -> Self {
+        return Self {
+            file_name: intent.file_name,
+            file_size: intent.file_size,
+            file_count: intent.file_count,
+            transfer_id: intent.transfer_id,
+            compression: intent.compression,
+        };
+    }
+}
+
 struct SharedVariables {
     receive_progress_delegate: Option<Box<dyn ReceiveProgressDelegate>>,
+    trust_level: TrustLevel,
+    sas_confirmed: Option<bool>,
+    receive_policy: ReceivePolicy,
+    receive_limits: ReceiveLimits,
 }
 
 #[derive(uniffi::Object)]
 pub struct ConnectionRequest {
     transfer_request: Request,
     connection: Arc<Mutex<Box<dyn EncryptedReadWrite>>>,
+    session: Session,
+    identity: Arc<DeviceIdentity>,
     file_storage: String,
     should_cancel: AtomicBool,
     variables: Arc<RwLock<SharedVariables>>,
@@ -45,45 +111,459 @@ impl ConnectionRequest {
     pub fn new(
         transfer_request: Request,
         connection: Box<dyn EncryptedReadWrite>,
+        session: Session,
+        identity: Arc<DeviceIdentity>,
         file_storage: String,
     ) -> Self {
+        // Nothing is pinned before the user accepted the request, see `pin_new_sender`
+        let trust_level = match &transfer_request.device {
+            Some(device) => trust_store()
+                .read()
+                .unwrap()
+                .check(&device.id, &session.peer_identity_key),
+            None => TrustLevel::New,
+        };
+
         Self {
             transfer_request,
             connection: Arc::new(Mutex::new(connection)),
+            session,
+            identity,
             file_storage,
             should_cancel: AtomicBool::new(false),
             variables: Arc::new(RwLock::new(SharedVariables {
                 receive_progress_delegate: None,
+                trust_level,
+                sas_confirmed: None,
+                receive_policy: ReceivePolicy::default(),
+                receive_limits: ReceiveLimits::default(),
             })),
         }
     }
 
+    fn file_transfer_intent(&self) -> Option<communication::FileTransferIntent> {
+        match self
+            .transfer_request
+            .intent
+            .clone()
+            .expect("Intent information missing")
+        {
+            Intent::FileTransfer(file_transfer_intent) => Some(file_transfer_intent),
+            Intent::Clipboard(_) => None,
+        }
+    }
+
+    /// Pins the sender's identity key once the user accepted a request from an unknown device.
+    /// A changed key is only replaced through `trust_sender` or `confirm_sas`.
+    fn pin_new_sender(&self) {
+        let Some(device) = &self.transfer_request.device else {
+            return;
+        };
+
+        let trust_level = trust_store()
+            .write()
+            .unwrap()
+            .check_and_pin(&device.id, &self.session.peer_identity_key);
+
+        if trust_level == TrustLevel::New {
+            self.variables.blocking_write().trust_level = TrustLevel::Verified;
+        }
+    }
+
     fn handle_file(
         &self,
         mut stream: MutexGuard<Box<dyn EncryptedReadWrite>>,
-        file_transfer: FileTransferIntent,
-    ) -> Option<Vec<String>> {
-        match untar_stream(
+        target: ExtractTarget,
+        compression: Compression,
+        declared: DeclaredSizes,
+        mut journal: TransferJournal,
+    ) -> Result<Vec<String>, ReceiveErrors> {
+        match unpack_transfer(
             &mut *stream,
-            self.file_storage.as_ref(),
-            file_transfer.file_size,
+            &target,
+            compression,
+            &declared,
             |progress| {
                 self.update_progress(ReceiveProgressState::Receiving { progress: progress });
             },
             &self.should_cancel,
+            &mut journal,
         ) {
-            Ok(files) => {
-                self.update_progress(ReceiveProgressState::Finished);
+            Ok(unpacked) => {
+                journal.finish();
+
+                self.acknowledge(
+                    &mut stream,
+                    Some(&unpacked.digests),
+                    unpacked.failed_files.clone(),
+                );
                 stream.close();
-                Some(files)
+
+                if !unpacked.failed_files.is_empty() {
+                    self.update_progress(ReceiveProgressState::IntegrityCheckFailed {
+                        file_paths: unpacked.failed_files.clone(),
+                    });
+
+                    return Err(ReceiveErrors::IntegrityCheckFailed {
+                        file_paths: unpacked.failed_files,
+                    });
+                }
+
+                self.update_progress(ReceiveProgressState::Finished);
+                Ok(unpacked.files)
             }
             Err(error) => {
-                error!("Error while unpacking: {}", error);
-                self.update_progress(ReceiveProgressState::Cancelled);
+                self.acknowledge(&mut stream, None, vec![]);
                 stream.close();
-                None
+
+                let receive_error = self.receive_error(&**stream, error);
+
+                // A broken connection keeps what was received, so the next attempt can continue.
+                // Everything else means the transfer is over.
+                let keep_for_resume = journal.is_resumable()
+                    && matches!(
+                        receive_error,
+                        ReceiveErrors::StreamTruncated | ReceiveErrors::FailedToReceive { .. }
+                    );
+
+                if !keep_for_resume {
+                    journal.finish();
+
+                    if let ExtractTarget::Directory { staging, .. } = target {
+                        staging.discard();
+                    }
+                }
+
+                Err(receive_error)
+            }
+        }
+    }
+
+    /// Tells the sender how the transfer ended, so it doesn't wait for the outcome.
+    fn acknowledge(
+        &self,
+        stream: &mut Box<dyn EncryptedReadWrite>,
+        digests: Option<&[u8]>,
+        failed_files: Vec<String>,
+    ) {
+        let acknowledgement =
+            sign_acknowledgement(&self.identity, &self.session, digests, failed_files);
+
+        let _ = Stream::new(&mut **stream).send(&acknowledgement);
+        let _ = stream.finish();
+    }
+
+    fn receive_error(&self, stream: &dyn EncryptedReadWrite, error: io::Error) -> ReceiveErrors {
+        if let Some(message) = stream.abort_message() {
+            let reason = TransferCancellation::decode(message.as_slice())
+                .ok()
+                .and_then(|cancellation| cancellation.reason);
+
+            info!("The sender cancelled the transfer: {:?}", reason);
+
+            self.update_progress(ReceiveProgressState::Cancelled {
+                reason: reason.clone(),
+            });
+
+            return ReceiveErrors::CancelledBySender { reason };
+        }
+
+        error!("Error while unpacking: {}", error);
+        self.update_progress(ReceiveProgressState::Cancelled { reason: None });
+
+        if let Some(integrity_error) = stream.integrity_error() {
+            return integrity_error.into();
+        }
+
+        if self.should_cancel.load(Ordering::Relaxed) {
+            return ReceiveErrors::Cancelled;
+        }
+
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<DeclaredSizeExceeded>())
+        {
+            return ReceiveErrors::DeclaredSizeExceeded;
+        }
+
+        if let Some(undeclared) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UndeclaredEntry>())
+        {
+            return ReceiveErrors::UndeclaredEntry {
+                path: undeclared.path.clone(),
+            };
+        }
+
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<MissingDigests>())
+        {
+            return ReceiveErrors::MissingDigests;
+        }
+
+        if let Some(unsafe_archive) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UnsafeArchiveError>())
+        {
+            return ReceiveErrors::UnsafeArchive {
+                error: unsafe_archive.to_string(),
+            };
+        }
+
+        return ReceiveErrors::FailedToReceive {
+            error: error.to_string(),
+        };
+    }
+
+    /// Checks the request against the receive limits and the free space at `destination`.
+    fn check_limits(
+        &self,
+        file_transfer: &communication::FileTransferIntent,
+        selection: &[u32],
+        destination: Option<&Path>,
+    ) -> Result<(), ReceiveErrors> {
+        let limits = self.variables.blocking_read().receive_limits;
+        let manifest = &file_transfer.manifest;
+        let selected: Vec<&FileManifestEntry> = selected_entries(manifest, selection)
+            .into_iter()
+            .map(|index| &manifest[index])
+            .collect();
+
+        let size = if selection.is_empty() {
+            file_transfer.file_size
+        } else {
+            selected.iter().map(|entry| entry.size).sum()
+        };
+
+        if let Some(limit) = limits.max_transfer_size.filter(|limit| size > *limit) {
+            return Err(ReceiveErrors::TransferTooLarge { size, limit });
+        }
+
+        if let Some(limit) = limits.max_file_size {
+            if let Some(entry) = selected.iter().find(|entry| entry.size > limit) {
+                return Err(ReceiveErrors::FileTooLarge {
+                    path: entry.path.clone(),
+                    size: entry.size,
+                    limit,
+                });
             }
         }
+
+        let Some(destination) = destination else {
+            return Ok(());
+        };
+
+        if limits.space_check == SpaceCheck::Ignore {
+            return Ok(());
+        }
+
+        let Some(available) = available_space(destination) else {
+            warn!("Failed to determine the free space of {:?}", destination);
+            return Ok(());
+        };
+
+        if available >= size {
+            return Ok(());
+        }
+
+        return match limits.space_check {
+            SpaceCheck::Decline => Err(ReceiveErrors::InsufficientSpace {
+                required: size,
+                available,
+            }),
+            SpaceCheck::Warn => {
+                self.update_progress(ReceiveProgressState::LowDiskSpace {
+                    required: size,
+                    available,
+                });
+                Ok(())
+            }
+            SpaceCheck::Ignore => Ok(()),
+        };
+    }
+
+    fn accept_selection(
+        &self,
+        selection: Vec<u32>,
+        destination: &Path,
+        sink: Option<&dyn NativeWriteDelegate>,
+    ) -> Result<Vec<String>, ReceiveErrors> {
+        if self.variables.blocking_read().sas_confirmed == Some(false) {
+            return Err(ReceiveErrors::VerificationFailed);
+        }
+
+        self.pin_new_sender();
+
+        if let Some(clipboard) = self.get_clipboard_intent() {
+            return self.receive_clipboard(clipboard).map(|_| vec![]);
+        }
+
+        let Some(file_transfer) = self.file_transfer_intent() else {
+            return Ok(vec![]);
+        };
+
+        let manifest = &file_transfer.manifest;
+        let receive_policy = self.variables.blocking_read().receive_policy;
+        let destination =
+            destination_dir(destination, receive_policy.layout, &self.get_sender().name);
+
+        // Files in a sink don't take up space at the destination
+        let space_destination = sink.is_none().then_some(destination.as_path());
+
+        if let Err(error) = self.check_limits(&file_transfer, &selection, space_destination) {
+            info!("Declining the transfer: {}", error);
+            self.decline();
+            return Err(error);
+        }
+
+        self.update_progress(ReceiveProgressState::Handshake);
+
+        // Files in a sink can't be appended to later, so those transfers are not resumable.
+        let resumable_id = file_transfer
+            .transfer_id
+            .as_deref()
+            .filter(|_| self.session.capabilities.resume && sink.is_none())
+            .and_then(|transfer_id| resume_id(&self.session.peer_identity_key, transfer_id));
+
+        let journal = match &resumable_id {
+            Some(resumable_id) => {
+                let staging_root = Staging::root(&destination, resumable_id);
+                TransferJournal::open(
+                    &destination,
+                    &staging_root,
+                    resumable_id,
+                    manifest,
+                    &selection,
+                )
+            }
+            None => TransferJournal::disabled(manifest),
+        };
+
+        let resume_from = journal.resume_from();
+
+        let staging = match sink {
+            Some(_) => None,
+            None => {
+                let resumable_id = resumable_id.as_deref().filter(|_| journal.is_resumable());
+
+                match Staging::open(&destination, resumable_id, resume_from.is_some()) {
+                    Ok(staging) => Some(staging),
+                    Err(error) => {
+                        error!("Failed to create the staging directory: {}", error);
+                        self.decline();
+                        return Err(ReceiveErrors::FailedToReceive {
+                            error: error.to_string(),
+                        });
+                    }
+                }
+            }
+        };
+        let total_bytes = if selection.is_empty() && resume_from.is_none() {
+            file_transfer.file_size
+        } else {
+            remaining_size(manifest, &selection, resume_from.as_ref())
+        };
+        let declared = DeclaredSizes {
+            total: total_bytes,
+            files: selected_entries(manifest, &selection)
+                .into_iter()
+                .map(|index| (manifest[index].path.clone(), manifest[index].size))
+                .collect(),
+        };
+
+        let offers_zstd = file_transfer
+            .compression()
+            .any(|compression| compression == Compression::Zstd);
+        let compression = if offers_zstd
+            && should_compress(
+                selected_entries(manifest, &selection)
+                    .into_iter()
+                    .map(|index| &manifest[index]),
+            ) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        };
+
+        if let Ok(mut connection_guard) = self.connection.lock() {
+            let mut stream = Stream::new(&mut *connection_guard);
+
+            let _ = stream.send(&TransferRequestResponse {
+                accepted: true,
+                selected_entries: selection,
+                resume_from,
+                compression: compression as i32,
+            });
+
+            let target = match (sink, &staging) {
+                (Some(sink), _) => ExtractTarget::Sink(sink),
+                (None, Some(staging)) => ExtractTarget::Directory {
+                    staging,
+                    collisions: receive_policy.collisions,
+                },
+                (None, None) => return Err(ReceiveErrors::ConnectionUnavailable),
+            };
+
+            self.handle_file(connection_guard, target, compression, declared, journal)
+        } else {
+            Err(ReceiveErrors::ConnectionUnavailable)
+        }
+    }
+
+    fn receive_clipboard(
+        &self,
+        clipboard: ClipboardTransferIntent,
+    ) -> Result<Vec<ClipboardItem>, ReceiveErrors> {
+        if total_size(&clipboard.representations) > MAX_CLIPBOARD_SIZE {
+            self.decline();
+            return Err(ReceiveErrors::ClipboardTooLarge);
+        }
+
+        let Ok(mut connection_guard) = self.connection.lock() else {
+            return Err(ReceiveErrors::ConnectionUnavailable);
+        };
+
+        let _ = Stream::new(&mut *connection_guard).send(&TransferRequestResponse {
+            accepted: true,
+            selected_entries: vec![],
+            resume_from: None,
+            compression: Compression::None as i32,
+        });
+
+        let items = match read_items(&mut *connection_guard, &clipboard.representations) {
+            Ok(items) => items,
+            Err(error) => {
+                error!("Error while receiving clipboard content: {}", error);
+                self.acknowledge(&mut connection_guard, None, vec![]);
+                connection_guard.close();
+
+                if let Some(integrity_error) = connection_guard.integrity_error() {
+                    return Err(integrity_error.into());
+                }
+
+                return Err(ReceiveErrors::FailedToReceive {
+                    error: error.to_string(),
+                });
+            }
+        };
+
+        self.acknowledge(
+            &mut connection_guard,
+            Some(&clipboard_digest(&clipboard.clipboard_content, &items)),
+            vec![],
+        );
+        connection_guard.close();
+
+        let mut representations = Vec::with_capacity(items.len() + 1);
+
+        if !clipboard.clipboard_content.is_empty() {
+            representations.push(plain_text_item(clipboard.clipboard_content));
+        }
+
+        representations.extend(items);
+
+        return Ok(representations);
     }
 
     pub fn get_intent(&self) -> Intent {
@@ -108,6 +588,40 @@ impl ConnectionRequest {
             .expect("Device information missing")
     }
 
+    /// Whether the identity key presented in the handshake matches the key pinned for the sender's device id.
+    pub fn get_sender_trust_level(&self) -> TrustLevel {
+        return self.variables.blocking_read().trust_level;
+    }
+
+    /// Pins the identity key presented by the sender, e.g. after the user confirmed a changed key.
+    pub fn trust_sender(&self) {
+        trust_store()
+            .write()
+            .unwrap()
+            .pin(&self.get_sender().id, &self.session.peer_identity_key);
+
+        self.variables.blocking_write().trust_level = TrustLevel::Verified;
+    }
+
+    /// Six digit short authentication string (SAS) derived from the handshake.
+    /// The sender reports the same code through `SendProgressState::Verification`.
+    pub fn get_sas(&self) -> String {
+        return self.session.short_authentication_string();
+    }
+
+    /// Records whether the user confirmed that both devices show the same SAS.
+    /// A match pins the sender's identity key, like `trust_sender`.
+    /// A mismatch means the connection was intercepted, so the request gets declined.
+    pub fn confirm_sas(&self, matches: bool) {
+        self.variables.blocking_write().sas_confirmed = Some(matches);
+
+        if matches {
+            self.trust_sender();
+        } else {
+            self.decline();
+        }
+    }
+
     pub fn get_intent_type(&self) -> ConnectionIntentType {
         match self
             .transfer_request
@@ -130,15 +644,21 @@ impl ConnectionRequest {
     }
 
     pub fn get_file_transfer_intent(&self) -> Option<FileTransferIntent> {
-        match self
-            .transfer_request
-            .intent
-            .clone()
-            .expect("Intent information missing")
-        {
-            Intent::FileTransfer(file_transfer_intent) => Some(file_transfer_intent),
-            Intent::Clipboard(_) => None,
-        }
+        return self.file_transfer_intent().map(FileTransferIntent::from);
+    }
+
+    /// Everything the sender is about to transfer, empty for clipboard shares.
+    pub fn get_manifest(&self) -> Vec<ManifestEntry> {
+        return self
+            .file_transfer_intent()
+            .map(|intent| {
+                intent
+                    .manifest
+                    .into_iter()
+                    .map(ManifestEntry::from)
+                    .collect()
+            })
+            .unwrap_or_default();
     }
 
     pub fn get_clipboard_intent(&self) -> Option<ClipboardTransferIntent> {
@@ -154,18 +674,15 @@ impl ConnectionRequest {
     }
 
     pub fn decline(&self) {
-        if self.get_intent_type() == ConnectionIntentType::Clipboard {
-            if let Ok(connection_guard) = self.connection.lock() {
-                connection_guard.close();
-            }
-
-            return;
-        }
-
         if let Ok(mut connection_guard) = self.connection.lock() {
             let mut stream = Stream::new(&mut *connection_guard);
 
-            let _ = stream.send(&TransferRequestResponse { accepted: false });
+            let _ = stream.send(&TransferRequestResponse {
+                accepted: false,
+                selected_entries: vec![],
+                resume_from: None,
+                compression: Compression::None as i32,
+            });
             connection_guard.close();
         }
     }
@@ -182,30 +699,69 @@ impl ConnectionRequest {
         self.should_cancel.store(true, Ordering::Relaxed);
     }
 
-    pub fn accept(&self) -> Option<Vec<String>> {
-        if self.get_intent_type() == ConnectionIntentType::Clipboard {
-            if let Ok(connection_guard) = self.connection.lock() {
-                connection_guard.close();
-            }
+    /// Receives the offered files into the storage directory and returns their paths.
+    /// If the connection breaks, what was received is kept, so accepting the same share again
+    /// continues where it stopped. Transfers that are not continued within a week are removed
+    /// once an `InternalNearbyServer` for the storage directory is created.
+    pub fn accept(&self) -> Result<Vec<String>, ReceiveErrors> {
+        return self.accept_selection(vec![], Path::new(&self.file_storage), None);
+    }
+
+    /// Like `accept`, but receives into `path` instead of the storage directory.
+    pub fn accept_to(&self, path: String) -> Result<Vec<String>, ReceiveErrors> {
+        return self.accept_selection(vec![], Path::new(&path), None);
+    }
 
-            return Some(vec![]);
+    /// Limits checked before a transfer is accepted. Requests exceeding them are declined.
+    pub fn set_receive_limits(&self, limits: ReceiveLimits) {
+        self.variables.blocking_write().receive_limits = limits;
+    }
+
+    /// Where received files are placed and how existing files are handled.
+    /// Applies to `accept`, `accept_to` and `accept_files`, but not to `accept_with_sink`.
+    pub fn set_receive_policy(&self, policy: ReceivePolicy) {
+        self.variables.blocking_write().receive_policy = policy;
+    }
+
+    /// Like `accept`, but writes the files through `sink` instead of into the storage directory.
+    /// Returns the locations reported by `create_file`. Files that fail the integrity check
+    /// are listed in `ReceiveErrors::IntegrityCheckFailed`, removing them is up to the sink.
+    pub fn accept_with_sink(
+        &self,
+        sink: Box<dyn NativeWriteDelegate>,
+    ) -> Result<Vec<String>, ReceiveErrors> {
+        return self.accept_selection(vec![], Path::new(&self.file_storage), Some(sink.as_ref()));
+    }
+
+    /// Accepts a clipboard share and returns all of its representations, the plain text first.
+    /// The host app can then pick the best one for the system clipboard.
+    pub fn accept_clipboard(&self) -> Result<Vec<ClipboardItem>, ReceiveErrors> {
+        if self.variables.blocking_read().sas_confirmed == Some(false) {
+            return Err(ReceiveErrors::VerificationFailed);
         }
 
-        self.update_progress(ReceiveProgressState::Handshake);
+        self.pin_new_sender();
 
-        if let Ok(mut connection_guard) = self.connection.lock() {
-            let mut stream = Stream::new(&mut *connection_guard);
+        let Some(clipboard) = self.get_clipboard_intent() else {
+            return Ok(vec![]);
+        };
 
-            let _ = stream.send(&TransferRequestResponse { accepted: true });
+        return self.receive_clipboard(clipboard);
+    }
 
-            match self.get_intent() {
-                Intent::FileTransfer(file_transfer) => {
-                    self.handle_file(connection_guard, file_transfer)
-                }
-                Intent::Clipboard(_) => None,
-            }
-        } else {
-            None
+    /// Like `accept`, but only receives the given manifest entries (see `get_manifest`).
+    /// Selecting a directory receives everything inside of it.
+    pub fn accept_files(&self, indices: Vec<u32>) -> Result<Vec<String>, ReceiveErrors> {
+        let manifest_length = self.get_manifest().len();
+
+        if indices.is_empty()
+            || indices
+                .iter()
+                .any(|index| *index as usize >= manifest_length)
+        {
+            return Err(ReceiveErrors::InvalidSelection);
         }
+
+        return self.accept_selection(indices, Path::new(&self.file_storage), None);
     }
 }
@@ -1,6 +1,7 @@
 use crate::encryption::generate_secure_base64_token;
 use crate::errors::DiscoverySetupError;
 use crate::init_logger;
+use crate::trust_store::{trust_store, TrustLevel};
 use log::{info, warn};
 use protocol::discovery;
 use protocol::discovery::device_discovery_message::Content;
@@ -104,6 +105,23 @@ impl InternalDiscovery {
             .collect()
     }
 
+    /// Compares the identity key advertised by a discovered device with the key pinned for it.
+    /// The advertised key is only proven during the handshake.
+    pub fn get_trust_level(self: Arc<Self>, device_id: String) -> TrustLevel {
+        let discovered_devices = self.discovered_devices.read().unwrap();
+
+        let Some(identity_key) = discovered_devices.get(&device_id).and_then(|device_info| {
+            <[u8; 32]>::try_from(device_info.identity_public_key.as_slice()).ok()
+        }) else {
+            return TrustLevel::New;
+        };
+
+        return trust_store()
+            .read()
+            .unwrap()
+            .check(&device_id, &identity_key);
+    }
+
     pub fn add_ble_implementation(
         self: Arc<Self>,
         implementation: Box<dyn BleDiscoveryImplementationDelegate>,
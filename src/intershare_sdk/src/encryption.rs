@@ -1,16 +1,96 @@
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
-use chacha20::cipher::{KeyIvInit, StreamCipher};
-use chacha20::XChaCha20;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, Payload};
+use chacha20poly1305::XChaCha20Poly1305;
+use hkdf::Hkdf;
 use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
 use std::io;
-use std::io::ErrorKind::Other;
+use std::io::ErrorKind::{ConnectionAborted, InvalidData, Other, UnexpectedEof};
 use std::io::{Error, Read, Write};
-use std::iter::repeat;
+use std::time::Duration;
 
+use crate::errors::StreamIntegrityError;
 use crate::stream::Close;
 
+/// Maximum amount of plaintext sealed into a single record.
+pub const MAX_RECORD_SIZE: usize = 16 * 1024;
+
+/// Size of the nonce prefix used by the STREAM construction (24 byte XNonce minus 5 byte counter).
+pub const NONCE_PREFIX_LENGTH: usize = 19;
+
+const TAG_LENGTH: usize = 16;
+const LAST_RECORD_FLAG: u32 = 1 << 31;
+/// Marks a final record that carries the reason for aborting the stream instead of data.
+const ABORT_RECORD_FLAG: u32 = 1 << 30;
+/// Associated data of abort records, so the flag can't be set on a regular final record.
+const ABORT_RECORD_AAD: &[u8] = b"intershare abort record";
+
+const SENDER_TO_RECEIVER_KEY_LABEL: &[u8] = b"intershare sender to receiver key";
+const SENDER_TO_RECEIVER_NONCE_LABEL: &[u8] = b"intershare sender to receiver nonce";
+const RECEIVER_TO_SENDER_KEY_LABEL: &[u8] = b"intershare receiver to sender key";
+const RECEIVER_TO_SENDER_NONCE_LABEL: &[u8] = b"intershare receiver to sender nonce";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HandshakeRole {
+    Sender,
+    Receiver,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DirectionKeys {
+    pub key: [u8; 32],
+    pub nonce: [u8; NONCE_PREFIX_LENGTH],
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SessionKeys {
+    pub sending: DirectionKeys,
+    pub receiving: DirectionKeys,
+}
+
+/// Derives one key and nonce prefix per direction from the X25519 shared secret.
+/// The transcript hash is used as HKDF salt, binding the keys to both public keys.
+pub fn derive_session_keys(
+    shared_secret: &[u8; 32],
+    transcript_hash: &[u8; 32],
+    role: HandshakeRole,
+) -> SessionKeys {
+    let hkdf = Hkdf::<Sha256>::new(Some(transcript_hash), shared_secret);
+
+    let expand_direction = |key_label: &[u8], nonce_label: &[u8]| {
+        let mut keys = DirectionKeys {
+            key: [0u8; 32],
+            nonce: [0u8; NONCE_PREFIX_LENGTH],
+        };
+
+        hkdf.expand(key_label, &mut keys.key)
+            .expect("32 bytes is a valid HKDF output length");
+        hkdf.expand(nonce_label, &mut keys.nonce)
+            .expect("19 bytes is a valid HKDF output length");
+
+        keys
+    };
+
+    let sender_to_receiver =
+        expand_direction(SENDER_TO_RECEIVER_KEY_LABEL, SENDER_TO_RECEIVER_NONCE_LABEL);
+    let receiver_to_sender =
+        expand_direction(RECEIVER_TO_SENDER_KEY_LABEL, RECEIVER_TO_SENDER_NONCE_LABEL);
+
+    return match role {
+        HandshakeRole::Sender => SessionKeys {
+            sending: sender_to_receiver,
+            receiving: receiver_to_sender,
+        },
+        HandshakeRole::Receiver => SessionKeys {
+            sending: receiver_to_sender,
+            receiving: sender_to_receiver,
+        },
+    };
+}
+
 pub fn generate_key() -> [u8; 32] {
-    let key = XChaCha20::generate_key(&mut OsRng);
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
 
     return key.into();
 }
@@ -21,17 +101,30 @@ pub fn generate_secure_base64_token(byte_length: usize) -> String {
     return URL_SAFE_NO_PAD.encode(&bytes);
 }
 
-pub fn generate_iv() -> [u8; 24] {
-    let nonce = XChaCha20::generate_iv(&mut OsRng);
+pub fn generate_iv() -> [u8; NONCE_PREFIX_LENGTH] {
+    let mut nonce = [0u8; NONCE_PREFIX_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
 
-    return nonce.into();
+    return nonce;
 }
 
+/// Authenticated record layer on top of a raw byte stream.
+///
+/// Every `write` call is sealed into its own record, framed as a big endian `u32` length
+/// (with the highest bit marking the final record and the second highest bit marking an abort)
+/// followed by the ciphertext and tag.
+/// Record nonces are derived by the STREAM construction, so reordering, replaying or
+/// dropping records fails authentication on the reading side.
 pub struct EncryptedStream<TStream>
 where
     TStream: Read + Write,
 {
-    pub cipher: XChaCha20,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    read_buffer: Vec<u8>,
+    read_position: usize,
+    integrity_error: Option<StreamIntegrityError>,
+    abort_message: Option<Vec<u8>>,
     pub raw_stream: TStream,
 }
 
@@ -39,14 +132,154 @@ impl<TStream> EncryptedStream<TStream>
 where
     TStream: Read + Write,
 {
-    pub fn new(key: [u8; 32], iv: [u8; 24], stream: TStream) -> Self {
-        let cipher = XChaCha20::new(&key.into(), &iv.into());
-
+    pub fn new(keys: SessionKeys, stream: TStream) -> Self {
         Self {
-            cipher,
+            encryptor: Some(EncryptorBE32::new(
+                &keys.sending.key.into(),
+                &keys.sending.nonce.into(),
+            )),
+            decryptor: Some(DecryptorBE32::new(
+                &keys.receiving.key.into(),
+                &keys.receiving.nonce.into(),
+            )),
+            read_buffer: Vec::new(),
+            read_position: 0,
+            integrity_error: None,
+            abort_message: None,
             raw_stream: stream,
         }
     }
+
+    /// Seals the final record of the outgoing direction.
+    /// The peer treats the end of the stream before this record as a truncation.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let Some(encryptor) = self.encryptor.take() else {
+            return Ok(());
+        };
+
+        let record = encryptor
+            .encrypt_last(&[][..])
+            .map_err(|_| Error::new(Other, "Failed to seal final record"))?;
+
+        self.write_record(&record, LAST_RECORD_FLAG)?;
+        return self.raw_stream.flush();
+    }
+
+    /// Ends the outgoing direction early. Unlike a truncated stream, the peer can tell
+    /// that this was intentional and gets the authenticated `message`.
+    pub fn abort(&mut self, message: &[u8]) -> io::Result<()> {
+        let Some(encryptor) = self.encryptor.take() else {
+            return Ok(());
+        };
+
+        let record = encryptor
+            .encrypt_last(Payload {
+                msg: message,
+                aad: ABORT_RECORD_AAD,
+            })
+            .map_err(|_| Error::new(Other, "Failed to seal abort record"))?;
+
+        self.write_record(&record, LAST_RECORD_FLAG | ABORT_RECORD_FLAG)?;
+        return self.raw_stream.flush();
+    }
+
+    pub fn integrity_error(&self) -> Option<StreamIntegrityError> {
+        return self.integrity_error;
+    }
+
+    /// Message of the abort record, if the peer aborted the stream.
+    pub fn abort_message(&self) -> Option<Vec<u8>> {
+        return self.abort_message.clone();
+    }
+
+    fn write_record(&mut self, record: &[u8], flags: u32) -> io::Result<()> {
+        let header = record.len() as u32 | flags;
+
+        self.raw_stream.write_all(&header.to_be_bytes())?;
+        return self.raw_stream.write_all(record);
+    }
+
+    fn fail(&mut self, error: StreamIntegrityError) -> Error {
+        self.integrity_error = Some(error);
+        self.decryptor = None;
+
+        let kind = match error {
+            StreamIntegrityError::Tampered => InvalidData,
+            StreamIntegrityError::Truncated => UnexpectedEof,
+        };
+
+        return Error::new(kind, error);
+    }
+
+    /// Reads and opens the next record. Returns `false` once the final record was consumed.
+    fn read_record(&mut self) -> io::Result<bool> {
+        if let Some(error) = self.integrity_error {
+            return Err(Error::new(Other, error));
+        }
+
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+
+        let mut header = [0u8; 4];
+
+        if let Err(error) = self.raw_stream.read_exact(&mut header) {
+            return Err(match error.kind() {
+                UnexpectedEof => self.fail(StreamIntegrityError::Truncated),
+                _ => error,
+            });
+        }
+
+        let header = u32::from_be_bytes(header);
+        let last = header & LAST_RECORD_FLAG != 0;
+        let abort = header & ABORT_RECORD_FLAG != 0;
+        let record_length = (header & !(LAST_RECORD_FLAG | ABORT_RECORD_FLAG)) as usize;
+
+        if (abort && !last) || !(TAG_LENGTH..=MAX_RECORD_SIZE + TAG_LENGTH).contains(&record_length)
+        {
+            return Err(self.fail(StreamIntegrityError::Tampered));
+        }
+
+        let mut record = vec![0u8; record_length];
+
+        if let Err(error) = self.raw_stream.read_exact(&mut record) {
+            return Err(match error.kind() {
+                UnexpectedEof => self.fail(StreamIntegrityError::Truncated),
+                _ => error,
+            });
+        }
+
+        let aad: &[u8] = if abort { ABORT_RECORD_AAD } else { &[] };
+
+        let decrypted = if last {
+            self.decryptor
+                .take()
+                .expect("Decryptor missing")
+                .decrypt_last(Payload {
+                    msg: record.as_slice(),
+                    aad,
+                })
+        } else {
+            self.decryptor
+                .as_mut()
+                .expect("Decryptor missing")
+                .decrypt_next(record.as_slice())
+        };
+
+        let Ok(plaintext) = decrypted else {
+            return Err(self.fail(StreamIntegrityError::Tampered));
+        };
+
+        if abort {
+            self.abort_message = Some(plaintext);
+            return Err(Error::new(ConnectionAborted, "The peer aborted the stream"));
+        }
+
+        self.read_buffer = plaintext;
+        self.read_position = 0;
+
+        return Ok(!last || !self.read_buffer.is_empty());
+    }
 }
 
 impl<TStream> Read for EncryptedStream<TStream>
@@ -54,21 +287,22 @@ where
     TStream: Read + Write,
 {
     fn read(&mut self, read_buffer: &mut [u8]) -> io::Result<usize> {
-        let mut buffer: Vec<u8> = repeat(0).take(read_buffer.len()).collect();
-        let read_bytes = self
-            .raw_stream
-            .read(&mut buffer)
-            .expect("Failed to read from encrypted buffer");
-
-        if read_bytes <= 0 {
+        if read_buffer.is_empty() {
             return Ok(0);
         }
 
-        self.cipher
-            .apply_keystream_b2b(&buffer[..read_bytes], &mut read_buffer[..read_bytes])
-            .map_err(|error| Error::new(Other, error.to_string()))?;
+        while self.read_position >= self.read_buffer.len() {
+            if !self.read_record()? {
+                return Ok(0);
+            }
+        }
+
+        let available = &self.read_buffer[self.read_position..];
+        let length = std::cmp::min(available.len(), read_buffer.len());
+        read_buffer[..length].copy_from_slice(&available[..length]);
+        self.read_position += length;
 
-        return Ok(read_bytes);
+        return Ok(length);
     }
 }
 
@@ -77,13 +311,23 @@ where
     TStream: Read + Write,
 {
     fn write(&mut self, write_buffer: &[u8]) -> io::Result<usize> {
-        let mut buffer: Vec<u8> = repeat(0).take(write_buffer.len()).collect();
-
-        let Ok(()) = self.cipher.apply_keystream_b2b(write_buffer, &mut buffer) else {
+        if write_buffer.is_empty() {
             return Ok(0);
+        }
+
+        let length = std::cmp::min(write_buffer.len(), MAX_RECORD_SIZE);
+
+        let Some(encryptor) = self.encryptor.as_mut() else {
+            return Err(Error::new(Other, "Stream was already finished"));
         };
 
-        return self.raw_stream.write(&buffer);
+        let record = encryptor
+            .encrypt_next(&write_buffer[..length])
+            .map_err(|_| Error::new(Other, "Failed to seal record"))?;
+
+        self.write_record(&record, 0)?;
+
+        return Ok(length);
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -98,10 +342,36 @@ where
     fn close(&self) {
         self.raw_stream.close();
     }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) {
+        self.raw_stream.set_read_timeout(timeout);
+    }
 }
 
-pub trait EncryptedReadWrite: Read + Write + Send + Close {}
-impl<TStream> EncryptedReadWrite for EncryptedStream<TStream> where
-    TStream: Read + Write + Send + Close
+pub trait EncryptedReadWrite: Read + Write + Send + Close {
+    fn finish(&mut self) -> io::Result<()>;
+    fn abort(&mut self, message: &[u8]) -> io::Result<()>;
+    fn integrity_error(&self) -> Option<StreamIntegrityError>;
+    fn abort_message(&self) -> Option<Vec<u8>>;
+}
+
+impl<TStream> EncryptedReadWrite for EncryptedStream<TStream>
+where
+    TStream: Read + Write + Send + Close,
 {
+    fn finish(&mut self) -> io::Result<()> {
+        return EncryptedStream::finish(self);
+    }
+
+    fn abort(&mut self, message: &[u8]) -> io::Result<()> {
+        return EncryptedStream::abort(self, message);
+    }
+
+    fn integrity_error(&self) -> Option<StreamIntegrityError> {
+        return EncryptedStream::integrity_error(self);
+    }
+
+    fn abort_message(&self) -> Option<Vec<u8>> {
+        return EncryptedStream::abort_message(self);
+    }
 }
@@ -22,6 +22,9 @@ pub enum ConnectErrors {
     #[error("Peripheral declined the connection")]
     Declined,
 
+    #[error("The receiver does not support this kind of share")]
+    UnsupportedIntent,
+
     #[error("Failed to get TCP connection details")]
     FailedToGetTcpDetails,
 
@@ -48,6 +51,151 @@ pub enum ConnectErrors {
 
     #[error("Failed to get transfer request response: {error}")]
     FailedToGetTransferRequestResponse { error: String },
+
+    #[error("The encrypted stream was tampered with")]
+    StreamTampered,
+
+    #[error("The encrypted stream ended unexpectedly")]
+    StreamTruncated,
+
+    #[error("The receiver reported files that did not match their digest")]
+    IntegrityCheckFailed,
+
+    #[error("The receiver's acknowledgement has an invalid signature")]
+    InvalidAcknowledgement,
+
+    #[error("The receiver could not finish the transfer")]
+    ReceiverFailed,
+
+    #[error("The receiver did not acknowledge the transfer: {error}")]
+    FailedToGetTransferAcknowledgement { error: String },
+
+    #[error("Failed to send files: {error}")]
+    FailedToSendFiles { error: String },
+
+    #[error("Failed to send clipboard content: {error}")]
+    FailedToSendClipboard { error: String },
+
+    #[error("The transfer was cancelled")]
+    Cancelled,
+
+    #[error("The receiver's identity key changed since the last connection")]
+    ReceiverKeyChanged,
+}
+
+impl From<StreamIntegrityError> for ConnectErrors {
+    fn from(error: StreamIntegrityError) -> Self {
+        match error {
+            StreamIntegrityError::Tampered => ConnectErrors::StreamTampered,
+            StreamIntegrityError::Truncated => ConnectErrors::StreamTruncated,
+        }
+    }
+}
+
+#[derive(Error, Debug, uniffi::Error)]
+pub enum ReceiveErrors {
+    #[error("The transfer was cancelled")]
+    Cancelled,
+
+    #[error("The sender cancelled the transfer: {reason:?}")]
+    CancelledBySender { reason: Option<String> },
+
+    #[error("The connection is no longer available")]
+    ConnectionUnavailable,
+
+    #[error("The short authentication string did not match the sender's")]
+    VerificationFailed,
+
+    #[error("The selection does not match the offered files")]
+    InvalidSelection,
+
+    #[error("The clipboard content is too large")]
+    ClipboardTooLarge,
+
+    #[error("The transfer ({size} bytes) exceeds the limit of {limit} bytes")]
+    TransferTooLarge { size: u64, limit: u64 },
+
+    #[error("{path} ({size} bytes) exceeds the limit of {limit} bytes per file")]
+    FileTooLarge { path: String, size: u64, limit: u64 },
+
+    #[error("Not enough free space: {required} bytes required, {available} bytes available")]
+    InsufficientSpace { required: u64, available: u64 },
+
+    #[error("The sender sent more data than it declared")]
+    DeclaredSizeExceeded,
+
+    #[error("The sender sent {path}, which was not part of the selected files")]
+    UndeclaredEntry { path: String },
+
+    #[error("The archive was rejected: {error}")]
+    UnsafeArchive { error: String },
+
+    #[error("Received files did not match the sender's digest: {file_paths:?}")]
+    IntegrityCheckFailed { file_paths: Vec<String> },
+
+    #[error("The sender did not send the digests of its files")]
+    MissingDigests,
+
+    #[error("The encrypted stream was tampered with")]
+    StreamTampered,
+
+    /// What was received is kept for the next attempt, see `ConnectionRequest::accept`.
+    #[error("The encrypted stream ended unexpectedly")]
+    StreamTruncated,
+
+    /// What was received is kept for the next attempt, see `ConnectionRequest::accept`.
+    #[error("Failed to receive: {error}")]
+    FailedToReceive { error: String },
+}
+
+impl From<StreamIntegrityError> for ReceiveErrors {
+    fn from(error: StreamIntegrityError) -> Self {
+        match error {
+            StreamIntegrityError::Tampered => ReceiveErrors::StreamTampered,
+            StreamIntegrityError::Truncated => ReceiveErrors::StreamTruncated,
+        }
+    }
+}
+
+/// Raised while unpacking, if an entry is larger than announced in the request.
+#[derive(Error, Debug)]
+#[error("{path} exceeds the size declared by the sender")]
+pub struct DeclaredSizeExceeded {
+    pub path: String,
+}
+
+/// Raised after unpacking, if the archive ended without the digests trailer.
+#[derive(Error, Debug)]
+#[error("The sender did not send the digests of its files")]
+pub struct MissingDigests;
+
+/// Raised while unpacking, if an entry was not declared in the request or not selected.
+#[derive(Error, Debug)]
+#[error("{path} was not declared by the sender")]
+pub struct UndeclaredEntry {
+    pub path: String,
+}
+
+/// Raised while unpacking an archive that would put the receiver at risk.
+#[derive(Error, Debug)]
+pub enum UnsafeArchiveError {
+    #[error("The archive has more than {limit} entries")]
+    TooManyEntries { limit: usize },
+
+    #[error("The path is too long: {path}")]
+    PathTooLong { path: String },
+
+    #[error("{path} would be written outside of the destination")]
+    OutsideDestination { path: String },
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+pub enum StreamIntegrityError {
+    #[error("Record authentication failed")]
+    Tampered,
+
+    #[error("Stream ended before the final record")]
+    Truncated,
 }
 
 #[derive(Error, Debug, uniffi::Error)]
@@ -60,6 +208,33 @@ pub enum RequestConvenienceShareErrors {
 
     #[error("Failed to connect")]
     FailedToConnect { error: String },
+
+    #[error("The share is no longer available")]
+    ShareNotFound,
+
+    #[error("The share was revoked")]
+    ShareRevoked,
+
+    #[error("The share has expired")]
+    ShareExpired,
+
+    #[error("The share reached its maximum number of downloads")]
+    ShareExhausted,
+
+    #[error("This device is not allowed to download the share")]
+    DeviceNotAllowed,
+
+    #[error("This share is protected by an access code")]
+    AccessCodeRequired,
+
+    #[error("Invalid access code")]
+    InvalidAccessCode,
+
+    #[error("The share is locked after too many wrong access codes")]
+    AccessCodeLocked,
+
+    #[error("Failed to receive the shared content: {error}")]
+    FailedToReceive { error: String },
 }
 
 #[derive(Error, Debug)]
@@ -82,6 +257,24 @@ pub enum IncomingErrors {
     #[error("Invalid foreign public key")]
     InvalidForeignPublicKey,
 
+    #[error("Invalid foreign identity key")]
+    InvalidIdentityKey,
+
+    #[error("Foreign identity signature does not match the handshake")]
+    InvalidIdentitySignature,
+
+    #[error("Foreign public key does not match its commitment")]
+    InvalidPublicKeyCommitment,
+
+    #[error("Invalid capabilities")]
+    InvalidCapabilities,
+
+    #[error("No common protocol version or cipher")]
+    IncompatibleCapabilities,
+
+    #[error("The sender uses protocol version 0, which is no longer supported")]
+    LegacyProtocolVersion,
+
     #[error("Error sending public key")]
     ErrorSendingPublicKey,
 
@@ -0,0 +1,89 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use log::{error, info, warn};
+use rand_core::OsRng;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const IDENTITY_FILE_NAME: &str = "identity.key";
+
+/// Long-term Ed25519 keypair identifying this device across sessions.
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+}
+
+impl DeviceIdentity {
+    pub fn generate() -> Self {
+        return Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        };
+    }
+
+    /// Loads the identity stored in `config_dir`, or creates and stores a new one.
+    /// Without a config directory, the identity only lives as long as this process.
+    pub fn load_or_create(config_dir: Option<PathBuf>) -> Self {
+        let Some(config_dir) = config_dir else {
+            warn!("No config directory available. Using an ephemeral device identity.");
+            return Self::generate();
+        };
+
+        let identity_path = config_dir.join(IDENTITY_FILE_NAME);
+
+        if let Ok(secret) = fs::read(&identity_path) {
+            if let Ok(secret) = <[u8; 32]>::try_from(secret.as_slice()) {
+                return Self {
+                    signing_key: SigningKey::from_bytes(&secret),
+                };
+            }
+
+            error!("Stored device identity is corrupted. Generating a new one.");
+        }
+
+        let identity = Self::generate();
+
+        match identity.store(&identity_path) {
+            Ok(()) => info!("Created new device identity"),
+            Err(error) => error!("Failed to store device identity: {}", error),
+        }
+
+        return identity;
+    }
+
+    fn store(&self, identity_path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = identity_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(identity_path)?;
+        return file.write_all(self.signing_key.as_bytes());
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        return self.signing_key.verifying_key().to_bytes();
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        return self.signing_key.sign(message).to_bytes();
+    }
+}
+
+pub fn verify_signature(public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(verifying_key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+
+    return verifying_key.verify_strict(message, &signature).is_ok();
+}
@@ -0,0 +1,181 @@
+use crate::communication::Session;
+use crate::identity::{verify_signature, DeviceIdentity};
+use log::{error, warn};
+use protocol::communication::TransferAcknowledgement;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+const ACKNOWLEDGEMENT_LABEL: &[u8] = b"intershare transfer acknowledgement";
+
+/// Vendor specific tar entry type of the trailer carrying the file digests.
+/// Receivers that don't know it skip it like any other unsupported entry.
+pub(crate) const DIGESTS_ENTRY_TYPE: u8 = b'I';
+
+/// Hashes everything read through it.
+pub(crate) struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        return Self {
+            inner,
+            hasher: Sha256::new(),
+        };
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        return self.hasher.finalize().into();
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..length]);
+
+        return Ok(length);
+    }
+}
+
+pub(crate) fn hash_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut reader = HashingReader::new(File::open(path)?);
+    io::copy(&mut reader, &mut io::sink())?;
+
+    return Ok(reader.finalize());
+}
+
+/// Re-reads the received files from disk and deletes the ones not matching the sender's digest,
+/// or without one. Returns the paths of the deleted files.
+pub(crate) fn verify_files(
+    digests: &HashMap<String, Vec<u8>>,
+    received_files: &HashMap<String, PathBuf>,
+) -> Vec<String> {
+    let mut failed_files = Vec::new();
+
+    for (path, target_path) in received_files {
+        let matches = match digests.get(path) {
+            Some(expected) => match hash_file(target_path) {
+                Ok(actual) => actual.as_slice() == expected.as_slice(),
+                Err(error) => {
+                    error!("Failed to hash received file {}: {}", path, error);
+                    false
+                }
+            },
+            None => {
+                warn!("No digest received for {}", path);
+                false
+            }
+        };
+
+        if !matches {
+            error!("Integrity check failed for {}", path);
+            let _ = fs::remove_file(target_path);
+            failed_files.push(target_path.to_string_lossy().to_string());
+        }
+    }
+
+    failed_files.sort();
+
+    return failed_files;
+}
+
+/// Compares files that were hashed while being written to a `NativeWriteDelegate`.
+/// They can't be read back, so the ones not matching or without a digest are only reported, by their location.
+pub(crate) fn verify_hashes(
+    digests: &HashMap<String, Vec<u8>>,
+    received_files: &HashMap<String, (String, [u8; 32])>,
+) -> Vec<String> {
+    let mut failed_files = Vec::new();
+
+    for (path, (location, actual)) in received_files {
+        let Some(expected) = digests.get(path) else {
+            warn!("No digest received for {}", path);
+            failed_files.push(location.clone());
+            continue;
+        };
+
+        if actual.as_slice() != expected.as_slice() {
+            error!("Integrity check failed for {}", path);
+            failed_files.push(location.clone());
+        }
+    }
+
+    failed_files.sort();
+
+    return failed_files;
+}
+
+fn acknowledgement_message(
+    session: &Session,
+    verified: bool,
+    digests: &[u8],
+    failed_files: &[String],
+) -> Vec<u8> {
+    // A failed transfer might have ended before the digests arrived
+    let digests_hash = Sha256::digest(if verified { digests } else { &[] });
+
+    let mut message = [
+        ACKNOWLEDGEMENT_LABEL,
+        session.transcript_hash.as_slice(),
+        &[verified as u8],
+        digests_hash.as_slice(),
+    ]
+    .concat();
+
+    // Sorted and length prefixed, so the order they were sent in doesn't matter
+    let mut failed_files: Vec<&String> = failed_files.iter().collect();
+    failed_files.sort();
+
+    for failed_file in failed_files {
+        message.extend_from_slice(&(failed_file.len() as u64).to_be_bytes());
+        message.extend_from_slice(failed_file.as_bytes());
+    }
+
+    return message;
+}
+
+/// Receipt for the sender, binding the outcome to the session and to the digests that were checked.
+/// For clipboard shares, `digests` is the digest of the accepted clipboard content.
+/// Without `digests` the transfer is reported as failed, e.g. after an error or a cancel.
+pub(crate) fn sign_acknowledgement(
+    identity: &DeviceIdentity,
+    session: &Session,
+    digests: Option<&[u8]>,
+    failed_files: Vec<String>,
+) -> TransferAcknowledgement {
+    let verified = digests.is_some() && failed_files.is_empty();
+    let signature = identity.sign(&acknowledgement_message(
+        session,
+        verified,
+        digests.unwrap_or_default(),
+        &failed_files,
+    ));
+
+    return TransferAcknowledgement {
+        verified,
+        failed_files,
+        signature: signature.to_vec(),
+    };
+}
+
+pub(crate) fn verify_acknowledgement(
+    acknowledgement: &TransferAcknowledgement,
+    session: &Session,
+    digests: &[u8],
+) -> bool {
+    return verify_signature(
+        &session.peer_identity_key,
+        &acknowledgement_message(
+            session,
+            acknowledgement.verified,
+            digests,
+            &acknowledgement.failed_files,
+        ),
+        &acknowledgement.signature,
+    );
+}
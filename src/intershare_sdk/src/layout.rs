@@ -0,0 +1,138 @@
+use crate::resume::JOURNAL_DIRECTORY;
+use icu_normalizer::ComposingNormalizerBorrowed;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where received files are placed below the destination directory.
+#[derive(uniffi::Enum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FolderLayout {
+    #[default]
+    Flat,
+    /// One folder per sender, named after the sender's device name.
+    PerSender,
+    /// One folder per day, named `YYYY-MM-DD` (UTC).
+    ByDate,
+}
+
+/// What happens if a received file or folder already exists at its destination.
+#[derive(uniffi::Enum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CollisionPolicy {
+    /// Keeps both by appending " (1)", " (2)", … to the received one.
+    #[default]
+    Rename,
+    Overwrite,
+    /// Keeps the existing file and drops the received one.
+    Skip,
+    /// Keeps whichever file was modified last, based on the modification time sent by the sender.
+    KeepNewest,
+}
+
+#[derive(uniffi::Record, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReceivePolicy {
+    pub layout: FolderLayout,
+    pub collisions: CollisionPolicy,
+}
+
+/// Applies the folder layout to the destination directory.
+pub(crate) fn destination_dir(base: &Path, layout: FolderLayout, sender_name: &str) -> PathBuf {
+    return match layout {
+        FolderLayout::Flat => base.to_path_buf(),
+        FolderLayout::PerSender => base.join(folder_name(sender_name)),
+        FolderLayout::ByDate => base.join(current_date()),
+    };
+}
+
+/// Names that Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turns a name chosen by the sender into one that is valid on every platform.
+/// Names are composed (NFC), as macOS treats composed and decomposed names as the same file.
+/// Characters Windows does not allow are replaced, trailing dots and spaces removed,
+/// and reserved device names like `CON` prefixed with "_". Returns an empty string
+/// if nothing is left.
+pub(crate) fn portable_name(name: &str) -> String {
+    let name = ComposingNormalizerBorrowed::new_nfc().normalize(name);
+
+    let name: String = name
+        .chars()
+        .map(|character| {
+            if character.is_control() || "/\\:*?\"<>|".contains(character) {
+                '_'
+            } else {
+                character
+            }
+        })
+        .collect();
+
+    let name = name.trim_end_matches(['.', ' ']);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return format!("_{}", name);
+    }
+
+    return name.to_string();
+}
+
+/// Device names are chosen by the sender, so they get the same treatment as received file names.
+fn folder_name(sender_name: &str) -> String {
+    let name = portable_name(sender_name.trim());
+
+    if name.is_empty() {
+        return "Unknown".to_string();
+    }
+
+    // Journals and staged files of transfers into the base directory are kept in this folder
+    if name.eq_ignore_ascii_case(JOURNAL_DIRECTORY) {
+        return format!("_{}", name);
+    }
+
+    return name;
+}
+
+fn current_date() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86400)
+        .unwrap_or_default() as i64;
+
+    // Civil date from days since the unix epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!("{:04}-{:02}-{:02}", year, month, day);
+}
+
+/// Whether a received file replaces the existing one at its destination, under `KeepNewest`.
+pub(crate) fn is_newer(existing: &Path, modified_at: u64) -> bool {
+    let Ok(existing_modified_at) = fs::metadata(existing).and_then(|metadata| metadata.modified())
+    else {
+        return true;
+    };
+
+    let existing_modified_at = existing_modified_at
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    return modified_at > existing_modified_at;
+}
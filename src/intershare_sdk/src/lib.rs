@@ -1,14 +1,13 @@
 use std::ffi::OsStr;
 use std::panic;
 use std::path::PathBuf;
+use std::sync::RwLock;
 
 // Only Android
 #[cfg(target_os = "android")]
 use android_logger::Config;
 #[cfg(target_os = "android")]
 use log::LevelFilter;
-#[cfg(target_os = "android")]
-use std::sync::RwLock;
 
 // If not Android
 #[cfg(not(target_os = "android"))]
@@ -24,38 +23,64 @@ use std::fs::File;
 #[cfg(not(target_os = "android"))]
 use std::sync::Once;
 
+pub use crate::clipboard::ClipboardItem;
 pub use crate::connection_request::{
-    ConnectionRequest, ReceiveProgressDelegate, ReceiveProgressState,
+    ConnectionRequest, FileTransferIntent, ReceiveProgressDelegate, ReceiveProgressState,
 };
 pub use crate::errors::ConnectErrors;
+pub use crate::layout::{CollisionPolicy, FolderLayout, ReceivePolicy};
+pub use crate::limits::{ReceiveLimits, SpaceCheck};
+pub use crate::manifest::{ManifestEntry, ManifestEntryKind, SharedData};
 pub use crate::nearby_server::ConnectionIntentType;
 pub use crate::nearby_server::{InternalNearbyServer, NearbyConnectionDelegate};
-pub use crate::protocol::communication::FileTransferIntent;
+pub use crate::protocol::communication::FileManifestEntry;
 pub use crate::protocol::discovery::{BluetoothLeConnectionInfo, TcpConnectionInfo};
 pub use crate::share_store::{
-    ConnectionMedium, SendProgressDelegate, SendProgressState, ShareStore,
+    ConnectionMedium, SendProgressDelegate, SendProgressState, SharePolicy, ShareStore,
 };
+pub use crate::trust_store::TrustLevel;
 pub use protocol;
-pub use protocol::communication::ClipboardTransferIntent;
+pub use protocol::communication::{ClipboardRepresentation, ClipboardTransferIntent};
 pub use protocol::discovery::Device;
 pub use thiserror::Error;
 
+pub mod access_code;
+pub mod capabilities;
+pub mod clipboard;
 pub mod communication;
+pub mod compression;
 pub mod connection;
 pub mod connection_request;
 pub mod discovery;
 pub mod encryption;
 pub mod errors;
+pub mod identity;
+mod integrity;
+pub mod layout;
+pub mod limits;
+pub mod manifest;
 pub mod nearby_server;
 mod progress;
+mod resume;
 pub mod share_store;
+mod staging;
 pub mod stream;
 mod tar;
 pub mod transmission;
+pub mod trust_store;
 #[cfg(target_os = "windows")]
 mod windows;
 
-pub const PROTOCOL_VERSION: u32 = 0;
+/// Internals the integration tests drive directly.
+#[cfg(feature = "test-utils")]
+pub mod test_utils {
+    pub use crate::tar::{untar_stream, DeclaredSizes, UnpackedTransfer};
+}
+
+/// Highest protocol version this build speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Lowest protocol version this build still speaks. Version 0 used the unauthenticated handshake.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
 pub const BLE_SERVICE_UUID: &str = "68D60EB2-8AAA-4D72-8851-BD6D64E169B7";
 pub const BLE_DISCOVERY_CHARACTERISTIC_UUID: &str = "0BEBF3FE-9A5E-4ED1-8157-76281B3F0DA5";
 pub const BLE_BUFFER_SIZE: usize = 10240;
@@ -73,24 +98,34 @@ pub fn get_ble_discovery_characteristic_uuid() -> String {
     return BLE_DISCOVERY_CHARACTERISTIC_UUID.to_string();
 }
 
-#[derive(uniffi::Enum)]
+#[derive(uniffi::Enum, Debug, PartialEq)]
 pub enum VersionCompatibility {
     Compatible,
     OutdatedVersion,
     IncompatibleNewVersion,
+    /// The device only speaks protocol version 0, whose unauthenticated handshake was removed.
+    UnsupportedLegacyVersion,
 }
 
 #[uniffi::export]
+/// Both devices are compatible, if their supported protocol version ranges overlap.
+/// Devices without a `min_protocol_version` only speak their `protocol_version`.
 pub fn is_compatible(device: Device) -> VersionCompatibility {
-    let Some(remote_device_version) = device.protocol_version else {
+    let Some(remote_max_version) = device.protocol_version else {
         return VersionCompatibility::OutdatedVersion;
     };
 
-    if remote_device_version < PROTOCOL_VERSION {
+    let remote_min_version = device.min_protocol_version.unwrap_or(remote_max_version);
+
+    if remote_max_version == 0 {
+        return VersionCompatibility::UnsupportedLegacyVersion;
+    }
+
+    if remote_max_version < MIN_PROTOCOL_VERSION {
         return VersionCompatibility::OutdatedVersion;
     }
 
-    if remote_device_version > PROTOCOL_VERSION {
+    if remote_min_version > PROTOCOL_VERSION {
         return VersionCompatibility::IncompatibleNewVersion;
     }
 
@@ -114,6 +149,34 @@ fn get_log_file_path() -> Option<PathBuf> {
     return None;
 }
 
+#[cfg(not(target_os = "android"))]
+pub(crate) fn get_config_dir() -> Option<PathBuf> {
+    if let Some(config_dir) = CONFIG_DIR.read().unwrap().clone() {
+        return Some(PathBuf::from(config_dir));
+    }
+
+    let project_dirs = BaseDirs::new()?;
+
+    return Some(project_dirs.config_dir().join("InterShare"));
+}
+
+#[cfg(target_os = "android")]
+pub(crate) fn get_config_dir() -> Option<PathBuf> {
+    return CONFIG_DIR.read().unwrap().clone().map(PathBuf::from);
+}
+
+/// Directory received files are staged in, along with the journals of resumable transfers.
+/// Without one, they are kept in a hidden folder inside the destination, see `set_tmp_dir`.
+#[cfg(not(target_os = "android"))]
+pub(crate) fn get_tmp_dir() -> Option<PathBuf> {
+    return None;
+}
+
+#[cfg(target_os = "android")]
+pub(crate) fn get_tmp_dir() -> Option<PathBuf> {
+    return TMP_DIR.read().unwrap().clone().map(PathBuf::from);
+}
+
 #[cfg(target_os = "android")]
 pub fn init_logger() {
     android_logger::init_once(Config::default().with_max_level(LevelFilter::Trace));
@@ -174,6 +237,9 @@ pub fn get_log_file_path_str() -> Option<String> {
 #[cfg(target_os = "android")]
 static TMP_DIR: RwLock<Option<String>> = RwLock::new(None);
 
+/// Sets the directory received files are written to until the transfer completed.
+/// It should be on the same volume as the storage directory, else files are copied into place.
+/// Interrupted transfers are kept there until they are continued, or for a week.
 #[cfg(target_os = "android")]
 #[uniffi::export]
 pub fn set_tmp_dir(tmp: String) {
@@ -181,4 +247,15 @@ pub fn set_tmp_dir(tmp: String) {
     *tmp_dir = Some(tmp);
 }
 
+static CONFIG_DIR: RwLock<Option<String>> = RwLock::new(None);
+
+/// Sets the directory used to persist the device identity and trusted device keys.
+/// Has to be called before `InternalNearbyServer` or `InternalDiscovery` are created.
+/// Required on Android, elsewhere it defaults to the user's config directory.
+#[uniffi::export]
+pub fn set_config_dir(config: String) {
+    let mut config_dir = CONFIG_DIR.write().unwrap();
+    *config_dir = Some(config);
+}
+
 uniffi::include_scaffolding!("intershare_sdk");
@@ -0,0 +1,68 @@
+use std::path::Path;
+
+/// What happens if the destination does not have enough free space for a transfer.
+#[derive(uniffi::Enum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpaceCheck {
+    /// Declines the transfer with `ReceiveErrors::InsufficientSpace`.
+    #[default]
+    Decline,
+    /// Reports `ReceiveProgressState::LowDiskSpace` and receives anyway.
+    Warn,
+    Ignore,
+}
+
+/// Checked against the sender's request before anything is received.
+#[derive(uniffi::Record, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReceiveLimits {
+    pub max_transfer_size: Option<u64>,
+    pub max_file_size: Option<u64>,
+    pub space_check: SpaceCheck,
+}
+
+/// Free space available to this process on the volume `path` is (or will be) on.
+pub fn available_space(path: &Path) -> Option<u64> {
+    // The destination might not exist yet, e.g. with a per sender folder layout
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+
+    return free_space(existing);
+}
+
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    return Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64));
+}
+
+#[cfg(windows)]
+fn free_space(path: &Path) -> Option<u64> {
+    use windows::core::HSTRING;
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let mut available = 0u64;
+
+    unsafe {
+        GetDiskFreeSpaceExW(
+            &HSTRING::from(path.as_os_str()),
+            Some(&mut available),
+            None,
+            None,
+        )
+    }
+    .ok()?;
+
+    return Some(available);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn free_space(_path: &Path) -> Option<u64> {
+    return None;
+}
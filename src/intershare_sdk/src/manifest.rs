@@ -0,0 +1,285 @@
+use crate::stream::{NativeReadDelegate, NativeReader};
+use crate::tar::normalize_path;
+use protocol::communication::file_manifest_entry::Kind;
+use protocol::communication::FileManifestEntry;
+use std::collections::HashSet;
+use std::fs::{File, Metadata};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+#[derive(uniffi::Enum, Clone, Copy, PartialEq, Debug)]
+pub enum ManifestEntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A single file, folder or symlink announced by the sender before the transfer starts.
+#[derive(uniffi::Record, Clone, Debug)]
+pub struct ManifestEntry {
+    /// Relative to the share root, separated by "/". The first component is the shared file or folder.
+    pub path: String,
+    pub size: u64,
+    pub kind: ManifestEntryKind,
+    pub mime_type: Option<String>,
+    /// Seconds since the unix epoch
+    pub modified_at: Option<u64>,
+}
+
+impl From<FileManifestEntry> for ManifestEntry {
+    fn from(entry: FileManifestEntry) -> Self {
+        let kind = match entry.kind() {
+            Kind::File => ManifestEntryKind::File,
+            Kind::Directory => ManifestEntryKind::Directory,
+            Kind::Symlink => ManifestEntryKind::Symlink,
+        };
+
+        return Self {
+            path: entry.path,
+            size: entry.size,
+            kind,
+            mime_type: entry.mime_type,
+            modified_at: entry.modified_at,
+        };
+    }
+}
+
+fn manifest_entry(path: String, metadata: &Metadata) -> FileManifestEntry {
+    let kind = if metadata.is_symlink() {
+        Kind::Symlink
+    } else if metadata.is_dir() {
+        Kind::Directory
+    } else {
+        Kind::File
+    };
+
+    let mime_type = match kind {
+        Kind::File => mime_guess::from_path(&path)
+            .first_raw()
+            .map(|mime_type| mime_type.to_string()),
+        _ => None,
+    };
+
+    let modified_at = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs());
+
+    return FileManifestEntry {
+        size: if kind == Kind::File {
+            metadata.len()
+        } else {
+            0
+        },
+        path,
+        kind: kind as i32,
+        mime_type,
+        modified_at,
+    };
+}
+
+/// Named byte buffer, shared as a file without writing it to disk first.
+#[derive(uniffi::Record, Clone, Debug)]
+pub struct SharedData {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Where the content of a manifest entry is read from.
+#[derive(Clone)]
+pub(crate) enum EntrySource {
+    Path(PathBuf),
+    Memory(Arc<Vec<u8>>),
+    Native(Arc<dyn NativeReadDelegate>),
+}
+
+impl EntrySource {
+    pub fn path(&self) -> Option<&Path> {
+        return match self {
+            EntrySource::Path(path) => Some(path),
+            _ => None,
+        };
+    }
+
+    pub fn open(&self, size: u64) -> io::Result<Box<dyn Read + '_>> {
+        return Ok(match self {
+            EntrySource::Path(path) => Box::new(File::open(path)?),
+            EntrySource::Memory(data) => Box::new(Cursor::new(data.as_slice())),
+            EntrySource::Native(delegate) => Box::new(NativeReader::new(delegate.clone(), size)),
+        });
+    }
+}
+
+/// Content that doesn't live in the file system, shared as a single file.
+#[derive(Clone)]
+pub(crate) struct DataSource {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the unix epoch. Stays the same across attempts, so interrupted transfers can be resumed.
+    pub modified_at: Option<u64>,
+    pub source: EntrySource,
+}
+
+impl DataSource {
+    pub fn new(name: String, size: u64, source: EntrySource) -> Self {
+        let modified_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs());
+
+        return Self {
+            name,
+            size,
+            modified_at,
+            source,
+        };
+    }
+}
+
+impl From<SharedData> for DataSource {
+    fn from(shared_data: SharedData) -> Self {
+        return DataSource::new(
+            shared_data.name,
+            shared_data.data.len() as u64,
+            EntrySource::Memory(Arc::new(shared_data.data)),
+        );
+    }
+}
+
+/// Manifest entry together with the source its content is read from.
+pub(crate) struct ManifestSource {
+    pub entry: FileManifestEntry,
+    pub source: EntrySource,
+}
+
+/// Recursively lists everything below `file_paths`, using the same paths as the tar stream.
+/// The shared paths themselves are resolved if they are symlinks, nested symlinks are not followed.
+/// Shared paths with the same name are made unique, see `unique_name`.
+pub(crate) fn scan(file_paths: &[String]) -> io::Result<Vec<ManifestSource>> {
+    let mut sources = Vec::new();
+    let mut used_names = HashSet::new();
+
+    for file_path in file_paths {
+        let root = Path::new(file_path);
+        let root_name = unique_name(&normalize_path(root), &mut used_names);
+
+        for entry in WalkDir::new(root).follow_links(false).sort_by_file_name() {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+
+            let path = relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .fold(root_name.clone(), |path, component| {
+                    format!("{}/{}", path, component)
+                });
+
+            let metadata = if entry.depth() == 0 {
+                root.metadata()?
+            } else {
+                entry.metadata()?
+            };
+
+            sources.push(ManifestSource {
+                entry: manifest_entry(path, &metadata),
+                source: EntrySource::Path(entry.into_path()),
+            });
+        }
+    }
+
+    return Ok(sources);
+}
+
+/// Numbers `name` if it was used already, like files with the same name on the receiver.
+fn unique_name(name: &str, used_names: &mut HashSet<String>) -> String {
+    let mut unique_name = name.to_string();
+    let mut counter = 1;
+
+    while !used_names.insert(unique_name.clone()) {
+        unique_name = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                format!("{} ({}).{}", stem, counter, extension)
+            }
+            _ => format!("{} ({})", name, counter),
+        };
+        counter += 1;
+    }
+
+    return unique_name;
+}
+
+/// Lists data sources as files at the top level of the share. Names are reduced to
+/// their last component and made unique, see `unique_name`.
+pub(crate) fn scan_data(data_sources: &[DataSource]) -> Vec<ManifestSource> {
+    let mut used_names = HashSet::new();
+
+    return data_sources
+        .iter()
+        .map(|data_source| {
+            let name = match normalize_path(Path::new(&data_source.name)).as_str() {
+                "" | "." | ".." => "file".to_string(),
+                name => name.to_string(),
+            };
+
+            let path = unique_name(&name, &mut used_names);
+
+            ManifestSource {
+                entry: FileManifestEntry {
+                    mime_type: mime_guess::from_path(&path)
+                        .first_raw()
+                        .map(|mime_type| mime_type.to_string()),
+                    path,
+                    size: data_source.size,
+                    kind: Kind::File as i32,
+                    modified_at: data_source.modified_at,
+                },
+                source: data_source.source.clone(),
+            }
+        })
+        .collect();
+}
+
+pub fn build_manifest(file_paths: &[String]) -> io::Result<Vec<FileManifestEntry>> {
+    return Ok(scan(file_paths)?
+        .into_iter()
+        .map(|source| source.entry)
+        .collect());
+}
+
+/// Indices of all entries covered by `selection`. Selecting a directory selects everything below it,
+/// an empty selection means the whole manifest. Out of range indices are ignored.
+pub fn selected_entries(manifest: &[FileManifestEntry], selection: &[u32]) -> Vec<usize> {
+    if selection.is_empty() {
+        return (0..manifest.len()).collect();
+    }
+
+    let selected_paths: Vec<&str> = selection
+        .iter()
+        .filter_map(|index| manifest.get(*index as usize))
+        .map(|entry| entry.path.as_str())
+        .collect();
+
+    return manifest
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            selected_paths.iter().any(|selected_path| {
+                entry.path == *selected_path
+                    || entry
+                        .path
+                        .strip_prefix(selected_path)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+        .map(|(index, _)| index)
+        .collect();
+}
+
+/// Sum of all file sizes, which is roughly the amount of data that will be transferred.
+pub fn total_size<'a>(manifest: impl IntoIterator<Item = &'a FileManifestEntry>) -> u64 {
+    return manifest.into_iter().map(|entry| entry.size).sum();
+}
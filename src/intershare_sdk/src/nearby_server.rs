@@ -1,25 +1,39 @@
-use crate::communication::initiate_receiver_communication;
+use crate::access_code::{confirmation_matches, AccessCodeExchange};
+use crate::clipboard::{
+    clipboard_files, representations, split_plain_text, total_size, ClipboardItem,
+    MAX_CLIPBOARD_SIZE,
+};
+use crate::communication::{initiate_receiver_communication, Session};
 use crate::connection::Connection;
 use crate::connection_request::ConnectionRequest;
+use crate::encryption::EncryptedReadWrite;
 use crate::errors::RequestConvenienceShareErrors;
-use crate::share_store::ShareStore;
+use crate::identity::DeviceIdentity;
+use crate::manifest::{DataSource, EntrySource, SharedData};
+use crate::resume::remove_stale_transfers;
+use crate::share_store::{ConnectionMedium, ShareStore};
 use crate::stream::Close;
-use crate::stream::NativeStreamDelegate;
+use crate::stream::{NativeReadDelegate, NativeStreamDelegate};
+use crate::transmission::http::HttpServer;
 use crate::transmission::tcp::TcpServer;
-use crate::{init_logger, PROTOCOL_VERSION};
+use crate::transmission::TransmissionSetupError;
+use crate::{get_config_dir, init_logger, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 use local_ip_address::local_ip;
-use log::{error, info};
+use log::{error, info, warn};
 use prost_stream::Stream;
+use protocol::communication::convenience_download_response::Status;
 use protocol::communication::request::RequestTypes;
-use protocol::communication::Request;
+use protocol::communication::{AccessCodeConfirmation, ConvenienceDownloadResponse, Request};
 use protocol::discovery::device_discovery_message::Content;
 use protocol::discovery::{
     BluetoothLeConnectionInfo, Device, DeviceConnectionInfo, DeviceDiscoveryMessage,
     TcpConnectionInfo,
 };
 use protocol::prost::Message;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
@@ -55,6 +69,77 @@ pub trait NearbyInstantReceiveDelegate: Send + Sync + Debug {
     fn requested_instant_file_receive(&self, device: Device, request_id: String) -> bool;
 }
 
+/// How many ended ids are remembered. Older ones are answered with "not found" again.
+const MAX_ENDED_REQUEST_IDS: usize = 256;
+
+/// Convenience shares that can currently be downloaded, keyed by their `request_id`.
+/// Revoked, cancelled, expired and exhausted shares are removed, but their ids are remembered
+/// with the reason, so their links fail with a dedicated error instead of "not found".
+#[derive(Default)]
+pub(crate) struct ShareRegistry {
+    share_stores: HashMap<String, Arc<ShareStore>>,
+    ended_request_ids: VecDeque<(String, Status)>,
+}
+
+pub(crate) type ShareStores = Arc<RwLock<ShareRegistry>>;
+
+impl ShareRegistry {
+    pub(crate) fn get(&self, request_id: &str) -> Option<Arc<ShareStore>> {
+        return self.share_stores.get(request_id).cloned();
+    }
+
+    /// Returns the share, or the status explaining why it can't be downloaded.
+    pub(crate) fn lookup(&mut self, request_id: &str) -> Result<Arc<ShareStore>, Status> {
+        self.evict_ended();
+
+        if let Some(share_store) = self.get(request_id) {
+            return Ok(share_store);
+        }
+
+        return Err(self
+            .ended_request_ids
+            .iter()
+            .find(|(id, _)| id == request_id)
+            .map_or(Status::NotFound, |(_, status)| *status));
+    }
+
+    /// Removes the shares that can no longer be downloaded, see `ShareStore::ended_status`.
+    pub(crate) fn evict_ended(&mut self) {
+        let ended: Vec<(String, Status)> = self
+            .share_stores
+            .iter()
+            .filter_map(|(request_id, share_store)| {
+                share_store
+                    .ended_status()
+                    .map(|status| (request_id.clone(), status))
+            })
+            .collect();
+
+        for (request_id, status) in ended {
+            info!("Removing ended share {}: {:?}", request_id, status);
+            self.end(&request_id, status);
+        }
+    }
+
+    fn revoke(&mut self, request_id: &str) -> bool {
+        return self.end(request_id, Status::Revoked);
+    }
+
+    fn end(&mut self, request_id: &str, status: Status) -> bool {
+        if self.share_stores.remove(request_id).is_none() {
+            return false;
+        }
+
+        if self.ended_request_ids.len() == MAX_ENDED_REQUEST_IDS {
+            self.ended_request_ids.pop_front();
+        }
+
+        self.ended_request_ids
+            .push_back((request_id.to_string(), status));
+        return true;
+    }
+}
+
 pub struct CurrentShareStore {
     pub request_id: String,
     pub file_paths: Option<Vec<String>>,
@@ -64,13 +149,15 @@ pub struct CurrentShareStore {
 #[derive(uniffi::Object)]
 pub struct InternalNearbyServer {
     pub(crate) tcp_server: RwLock<Option<TcpServer>>,
+    http_server: RwLock<Option<HttpServer>>,
     ble_server_implementation: RwLock<Option<Box<dyn BleServerImplementationDelegate>>>,
     ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>,
+    pub(crate) identity: Arc<DeviceIdentity>,
     pub advertise: RwLock<bool>,
     file_storage: String,
     pub device_connection_info: RwLock<DeviceConnectionInfo>,
     nearby_connection_delegate: Option<Arc<RwLock<Box<dyn NearbyConnectionDelegate>>>>,
-    pub(crate) current_share_store: Arc<RwLock<Option<Arc<ShareStore>>>>,
+    pub(crate) share_stores: ShareStores,
 
     #[cfg(target_os = "windows")]
     pub(crate) gatt_service_provider: std::sync::RwLock<Option<GattServiceProvider>>,
@@ -90,11 +177,15 @@ impl InternalNearbyServer {
 
         let mut my_device = my_device.clone();
         my_device.protocol_version = Some(PROTOCOL_VERSION);
+        my_device.min_protocol_version = Some(MIN_PROTOCOL_VERSION);
+
+        let identity = Arc::new(DeviceIdentity::load_or_create(get_config_dir()));
 
         let device_connection_info = DeviceConnectionInfo {
             device: Some(my_device),
             ble: None,
             tcp: None,
+            identity_public_key: identity.public_key().to_vec(),
         };
 
         let nearby_connection_delegate = match delegate {
@@ -102,15 +193,21 @@ impl InternalNearbyServer {
             None => None,
         };
 
+        // Scanning the storage directory shouldn't hold up the caller
+        let storage_dir = PathBuf::from(&file_storage);
+        std::thread::spawn(move || remove_stale_transfers(&storage_dir));
+
         return Self {
             tcp_server: RwLock::new(None),
+            http_server: RwLock::new(None),
             ble_server_implementation: RwLock::new(None),
             ble_l2_cap_client: Arc::new(RwLock::new(None)),
+            identity,
             advertise: RwLock::new(false),
             file_storage,
             device_connection_info: RwLock::new(device_connection_info),
             nearby_connection_delegate,
-            current_share_store: Arc::new(RwLock::new(None)),
+            share_stores: Arc::new(RwLock::new(ShareRegistry::default())),
 
             #[cfg(target_os = "windows")]
             gatt_service_provider: std::sync::RwLock::new(None),
@@ -159,6 +256,7 @@ impl InternalNearbyServer {
     pub fn change_device(&self, new_device: Device) {
         let mut device = new_device.clone();
         device.protocol_version = Some(PROTOCOL_VERSION);
+        device.min_protocol_version = Some(MIN_PROTOCOL_VERSION);
         self.device_connection_info.blocking_write().device = Some(device);
     }
 
@@ -185,7 +283,8 @@ impl InternalNearbyServer {
     pub async fn request_download(
         &self,
         link: String,
-    ) -> Result<(), RequestConvenienceShareErrors> {
+        access_code: Option<String>,
+    ) -> Result<Arc<ConnectionRequest>, RequestConvenienceShareErrors> {
         let parsed_url =
             Url::parse(&link).map_err(|_| RequestConvenienceShareErrors::NotAValidLink)?;
 
@@ -219,6 +318,14 @@ impl InternalNearbyServer {
             .parse::<u32>()
             .map_err(|_| RequestConvenienceShareErrors::NotAValidLink)?;
 
+        let requires_access_code = parsed_url
+            .query_pairs()
+            .any(|(key, value)| key == "c" && value == "1");
+
+        if requires_access_code && access_code.is_none() {
+            return Err(RequestConvenienceShareErrors::AccessCodeRequired);
+        }
+
         // let device_id = parsed_url
         //     .query_pairs()
         //     .find(|(key, _)| key == "d")
@@ -235,28 +342,39 @@ impl InternalNearbyServer {
         //     .ok_or(RequestConvenienceShareErrors::NotAValidLink)
         //     ?.to_string();
 
-        let connection = Connection::new(self.ble_l2_cap_client.clone());
+        let connection = Connection::new(self.ble_l2_cap_client.clone(), self.identity.clone());
 
         let connection_details = DeviceConnectionInfo {
             device: None,
             tcp: Some(TcpConnectionInfo { hostname: ip, port }),
             ble: None,
+            identity_public_key: vec![],
         };
 
-        let mut encrypted_stream = match connection.connect_tcp(&connection_details).await {
-            Ok(connection) => connection,
-            Err(err) => {
-                error!("Error while trying to connect: {:?}", err);
-                return Err(RequestConvenienceShareErrors::FailedToConnect {
-                    error: err.to_string(),
-                });
+        let (mut encrypted_stream, session) =
+            match connection.connect_tcp(&connection_details).await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    error!("Error while trying to connect: {:?}", err);
+                    return Err(RequestConvenienceShareErrors::FailedToConnect {
+                        error: err.to_string(),
+                    });
+                }
+            };
+
+        let (access_code_exchange, access_code_message) = match &access_code {
+            Some(access_code) => {
+                let (exchange, message) = AccessCodeExchange::start(access_code, &session);
+                (Some(exchange), Some(message))
             }
+            None => (None, None),
         };
 
         let request = Request {
             r#type: RequestTypes::ConvenienceDownloadRequest as i32,
             device: self.device_connection_info.read().await.device.clone(),
             share_id: Some(id.clone()),
+            access_code_message,
             intent: None,
         };
 
@@ -265,7 +383,87 @@ impl InternalNearbyServer {
         let mut proto_stream = Stream::new(&mut encrypted_stream);
         let _ = proto_stream.send(&request);
 
-        return Ok(());
+        let response = proto_stream
+            .recv::<ConvenienceDownloadResponse>()
+            .map_err(|error| RequestConvenienceShareErrors::FailedToConnect {
+                error: error.to_string(),
+            })?;
+
+        match response.status() {
+            Status::Accepted => {}
+            Status::NotFound => return Err(RequestConvenienceShareErrors::ShareNotFound),
+            Status::AccessCodeRequired => {
+                return Err(RequestConvenienceShareErrors::AccessCodeRequired)
+            }
+            Status::Revoked => return Err(RequestConvenienceShareErrors::ShareRevoked),
+            Status::Expired => return Err(RequestConvenienceShareErrors::ShareExpired),
+            Status::Exhausted => return Err(RequestConvenienceShareErrors::ShareExhausted),
+            Status::DeviceNotAllowed => {
+                return Err(RequestConvenienceShareErrors::DeviceNotAllowed)
+            }
+            Status::Locked => return Err(RequestConvenienceShareErrors::AccessCodeLocked),
+            Status::InvalidAccessCode => {
+                return Err(RequestConvenienceShareErrors::InvalidAccessCode)
+            }
+        }
+
+        if let Some(foreign_message) = &response.access_code_message {
+            let Some(access_code_exchange) = access_code_exchange else {
+                return Err(RequestConvenienceShareErrors::AccessCodeRequired);
+            };
+
+            let Some(confirmations) = access_code_exchange.finish(foreign_message) else {
+                return Err(RequestConvenienceShareErrors::InvalidAccessCode);
+            };
+
+            // The sharer only confirms its code after ours was checked
+            let _ = proto_stream.send(&AccessCodeConfirmation {
+                access_code_confirmation: confirmations.downloader.to_vec(),
+            });
+
+            let confirmation = proto_stream
+                .recv::<ConvenienceDownloadResponse>()
+                .map_err(|error| RequestConvenienceShareErrors::FailedToConnect {
+                    error: error.to_string(),
+                })?;
+
+            match confirmation.status() {
+                Status::Accepted => {}
+                Status::Locked => return Err(RequestConvenienceShareErrors::AccessCodeLocked),
+                _ => return Err(RequestConvenienceShareErrors::InvalidAccessCode),
+            }
+
+            if !confirmation_matches(
+                &confirmations.sharer,
+                confirmation.access_code_confirmation(),
+            ) {
+                return Err(RequestConvenienceShareErrors::InvalidAccessCode);
+            }
+        }
+
+        let transfer_request = proto_stream.recv::<Request>().map_err(|error| {
+            RequestConvenienceShareErrors::FailedToReceive {
+                error: error.to_string(),
+            }
+        })?;
+
+        if transfer_request.r#type != RequestTypes::ShareRequest as i32
+            || transfer_request.intent.is_none()
+        {
+            return Err(RequestConvenienceShareErrors::FailedToReceive {
+                error: "Received an invalid transfer request".to_string(),
+            });
+        }
+
+        let connection_request = ConnectionRequest::new(
+            transfer_request,
+            encrypted_stream,
+            session,
+            self.identity.clone(),
+            self.file_storage.clone(),
+        );
+
+        return Ok(Arc::new(connection_request));
     }
 
     pub async fn start(&self) {
@@ -323,20 +521,98 @@ impl InternalNearbyServer {
         self.start().await;
     }
 
-    pub async fn share_text(&self, text: String, allow_convenience_share: bool) -> Arc<ShareStore> {
+    pub async fn share_text(
+        &self,
+        text: String,
+        allow_convenience_share: bool,
+        access_code: Option<String>,
+    ) -> Arc<ShareStore> {
         let share_store = Arc::new(ShareStore::new(
             None,
             Some(text),
             allow_convenience_share,
+            access_code,
             self.ble_l2_cap_client.clone(),
+            self.identity.clone(),
             self.device_connection_info.read().await.clone(),
         ));
 
-        *self.current_share_store.write().await = Some(share_store.clone());
+        self.register_share_store(share_store.clone()).await;
+
+        return share_store;
+    }
+
+    /// Shares several representations of the clipboard content at once, e.g. plain text and HTML.
+    /// Receivers without support for them only get the plain text.
+    /// Content larger than `MAX_CLIPBOARD_SIZE` is shared as files instead, one per representation.
+    pub async fn share_clipboard(
+        &self,
+        items: Vec<ClipboardItem>,
+        allow_convenience_share: bool,
+        access_code: Option<String>,
+    ) -> Arc<ShareStore> {
+        if total_size(&representations(&items)) > MAX_CLIPBOARD_SIZE {
+            let data_sources = clipboard_files(items)
+                .into_iter()
+                .map(DataSource::from)
+                .collect();
+
+            return self
+                .share_data_sources(data_sources, allow_convenience_share, access_code)
+                .await;
+        }
+
+        let (text, items) = split_plain_text(items);
+
+        let share_store = Arc::new(
+            ShareStore::new(
+                None,
+                Some(text.unwrap_or_default()),
+                allow_convenience_share,
+                access_code,
+                self.ble_l2_cap_client.clone(),
+                self.identity.clone(),
+                self.device_connection_info.read().await.clone(),
+            )
+            .with_clipboard_items(items),
+        );
+
+        self.register_share_store(share_store.clone()).await;
 
         return share_store;
     }
 
+    /// Shares byte buffers as files, without writing them to disk first.
+    pub async fn share_data(
+        &self,
+        data: Vec<SharedData>,
+        allow_convenience_share: bool,
+        access_code: Option<String>,
+    ) -> Arc<ShareStore> {
+        let data_sources = data.into_iter().map(DataSource::from).collect();
+
+        return self
+            .share_data_sources(data_sources, allow_convenience_share, access_code)
+            .await;
+    }
+
+    /// Shares a single file of `size` bytes, read through `reader`.
+    /// Useful for content that has no file path, like Android content URIs.
+    pub async fn share_stream(
+        &self,
+        name: String,
+        size: u64,
+        reader: Box<dyn NativeReadDelegate>,
+        allow_convenience_share: bool,
+        access_code: Option<String>,
+    ) -> Arc<ShareStore> {
+        let data_source = DataSource::new(name, size, EntrySource::Native(Arc::from(reader)));
+
+        return self
+            .share_data_sources(vec![data_source], allow_convenience_share, access_code)
+            .await;
+    }
+
     pub fn handle_incoming_connection(&self, native_stream_handle: Box<dyn NativeStreamDelegate>) {
         self.handle_incoming_connection_generic(native_stream_handle);
     }
@@ -345,40 +621,88 @@ impl InternalNearbyServer {
         &self,
         file_paths: Vec<String>,
         allow_convenience_share: bool,
+        access_code: Option<String>,
     ) -> Arc<ShareStore> {
         let share_store = Arc::new(ShareStore::new(
             Some(file_paths),
             None,
             allow_convenience_share,
+            access_code,
             self.ble_l2_cap_client.clone(),
+            self.identity.clone(),
             self.device_connection_info.read().await.clone(),
         ));
 
-        *self.current_share_store.write().await = Some(share_store.clone());
+        self.register_share_store(share_store.clone()).await;
 
         return share_store;
     }
 
-    // pub(crate) async fn received_convenience_download_request(request: Request, current_share_store: Arc<RwLock<Option<Arc<ShareStore>>>>) {
-    //     let Some(current_share_store) = &*current_share_store.read().await else {
-    //         return;
-    //     };
-    //
-    //     if request.share_id != Some(current_share_store.request_id.clone()) {
-    //         warn!("Received convenience download request, but wrong with a wrong ID. Expected: {}, but received {:?}", current_share_store.request_id.clone(), request.share_id());
-    //         return;
-    //     }
-    //
-    //     let Some(device) = request.device else {
-    //         return;
-    //     };
-    //
-    //     let _ = current_share_store.send_to(device, None).await;
-    // }
+    /// All shares which can currently be downloaded via their convenience link.
+    pub async fn get_share_stores(&self) -> Vec<Arc<ShareStore>> {
+        let mut registry = self.share_stores.write().await;
+        registry.evict_ended();
+
+        return registry.share_stores.values().cloned().collect();
+    }
+
+    pub async fn get_share_store(&self, request_id: String) -> Option<Arc<ShareStore>> {
+        return self.share_stores.write().await.lookup(&request_id).ok();
+    }
+
+    /// Invalidates the convenience link of the given share. Returns `false` if it was not registered.
+    pub async fn revoke_share_store(&self, request_id: String) -> bool {
+        return self.share_stores.write().await.revoke(&request_id);
+    }
+
+    pub async fn revoke_all_share_stores(&self) {
+        let mut registry = self.share_stores.write().await;
+        let request_ids: Vec<String> = registry.share_stores.keys().cloned().collect();
+
+        for request_id in request_ids {
+            registry.revoke(&request_id);
+        }
+    }
+
+    /// Serves convenience shares to browsers on the local network,
+    /// which allows downloading them without the app. See `ShareStore::generate_http_link`.
+    pub async fn start_http_server(&self) -> Result<u16, TransmissionSetupError> {
+        if let Some(http_server) = &*self.http_server.read().await {
+            return Ok(http_server.port);
+        }
+
+        let http_server = self.new_http_server().map_err(|error| {
+            TransmissionSetupError::UnableToStartHttpServer {
+                error: error.to_string(),
+            }
+        })?;
+
+        let port = http_server.port;
+        *self.http_server.write().await = Some(http_server);
+
+        for share_store in self.get_share_stores().await {
+            share_store.set_http_port(Some(port));
+        }
+
+        return Ok(port);
+    }
+
+    pub async fn stop_http_server(&self) {
+        let Some(http_server) = self.http_server.write().await.take() else {
+            return;
+        };
+
+        http_server.stop();
+
+        for share_store in self.get_share_stores().await {
+            share_store.set_http_port(None);
+        }
+    }
 
     pub async fn stop(&self) {
         *self.advertise.write().await = false;
         self.stop_tcp_server().await;
+        self.stop_http_server().await;
 
         *self.tcp_server.write().await = None;
 
@@ -400,6 +724,47 @@ impl InternalNearbyServer {
 }
 
 impl InternalNearbyServer {
+    async fn share_data_sources(
+        &self,
+        data_sources: Vec<DataSource>,
+        allow_convenience_share: bool,
+        access_code: Option<String>,
+    ) -> Arc<ShareStore> {
+        let share_store = Arc::new(
+            ShareStore::new(
+                Some(vec![]),
+                None,
+                allow_convenience_share,
+                access_code,
+                self.ble_l2_cap_client.clone(),
+                self.identity.clone(),
+                self.device_connection_info.read().await.clone(),
+            )
+            .with_data_sources(data_sources),
+        );
+
+        self.register_share_store(share_store.clone()).await;
+
+        return share_store;
+    }
+
+    /// Only shares with a convenience link are kept, everything else is sent directly via `send_to`.
+    async fn register_share_store(&self, share_store: Arc<ShareStore>) {
+        if !share_store.allows_convenience_share() {
+            return;
+        }
+
+        if let Some(http_server) = &*self.http_server.read().await {
+            share_store.set_http_port(Some(http_server.port));
+        }
+
+        let mut registry = self.share_stores.write().await;
+        registry.evict_ended();
+        registry
+            .share_stores
+            .insert(share_store.request_id.clone(), share_store);
+    }
+
     fn handle_incoming_connection_generic<T>(&self, native_stream_handle: T)
     where
         T: Read + Write + Send + Close + 'static,
@@ -411,20 +776,33 @@ impl InternalNearbyServer {
         };
 
         let file_storage = self.file_storage.clone();
-        // let current_share_store = self.current_share_store.clone();
+        let identity = self.identity.clone();
+        let share_stores = self.share_stores.clone();
 
         if Handle::try_current().is_err() {
             // Create a new runtime if one doesn't exist
             let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
             rt.spawn(async move {
-                Self::process_incoming_connection(native_stream_handle, delegate, file_storage)
-                    .await;
+                Self::process_incoming_connection(
+                    native_stream_handle,
+                    delegate,
+                    identity,
+                    share_stores,
+                    file_storage,
+                )
+                .await;
             });
         } else {
             // Already in a Tokio runtime
             tokio::spawn(async move {
-                Self::process_incoming_connection(native_stream_handle, delegate, file_storage)
-                    .await;
+                Self::process_incoming_connection(
+                    native_stream_handle,
+                    delegate,
+                    identity,
+                    share_stores,
+                    file_storage,
+                )
+                .await;
             });
         }
     }
@@ -432,17 +810,20 @@ impl InternalNearbyServer {
     async fn process_incoming_connection<T>(
         native_stream_handle: T,
         delegate: Arc<RwLock<Box<dyn NearbyConnectionDelegate>>>,
+        identity: Arc<DeviceIdentity>,
+        share_stores: ShareStores,
         file_storage: String,
     ) where
         T: Read + Write + Send + Close + 'static,
     {
-        let mut encrypted_stream = match initiate_receiver_communication(native_stream_handle) {
-            Ok(request) => request,
-            Err(error) => {
-                error!("Encryption error {:}", error);
-                return;
-            }
-        };
+        let (mut encrypted_stream, session) =
+            match initiate_receiver_communication(native_stream_handle, &identity) {
+                Ok(result) => result,
+                Err(error) => {
+                    error!("Encryption error {:}", error);
+                    return;
+                }
+            };
 
         info!("Received encrypted connection request.");
 
@@ -456,8 +837,13 @@ impl InternalNearbyServer {
         };
 
         if request.r#type == RequestTypes::ShareRequest as i32 {
-            let connection_request =
-                ConnectionRequest::new(request, Box::new(encrypted_stream), file_storage.clone());
+            let connection_request = ConnectionRequest::new(
+                request,
+                Box::new(encrypted_stream),
+                session,
+                identity.clone(),
+                file_storage.clone(),
+            );
 
             info!("Sending received_connection_request delegate.");
             delegate
@@ -465,7 +851,61 @@ impl InternalNearbyServer {
                 .await
                 .received_connection_request(Arc::new(connection_request));
         } else {
-            // NearbyServer::received_convenience_download_request(request, current_share_store).await;
+            tokio::task::spawn_blocking(move || {
+                Self::received_convenience_download_request(
+                    request,
+                    Box::new(encrypted_stream),
+                    session,
+                    ConnectionMedium::BLE,
+                    share_stores,
+                );
+            });
+        }
+    }
+
+    /// Blocks until the download finished, so it has to run on a blocking thread.
+    pub(crate) fn received_convenience_download_request(
+        request: Request,
+        mut encrypted_stream: Box<dyn EncryptedReadWrite>,
+        session: Session,
+        medium: ConnectionMedium,
+        share_stores: ShareStores,
+    ) {
+        let share_store = share_stores.blocking_write().lookup(request.share_id());
+
+        let share_store = match share_store {
+            Ok(share_store) => share_store,
+            Err(status) => {
+                warn!(
+                    "Received convenience download request for an unavailable share: {:?}",
+                    request.share_id
+                );
+
+                let _ = Stream::new(&mut encrypted_stream).send(&ConvenienceDownloadResponse {
+                    status: status as i32,
+                    access_code_message: None,
+                    access_code_confirmation: None,
+                });
+
+                return;
+            }
+        };
+
+        // Until here, a stalled downloader would hold on to a download or access code attempt.
+        if !share_store.authorize_download(&request, &mut encrypted_stream, &session) {
+            return;
+        }
+
+        info!("Authorized convenience download request.");
+
+        // Accepting the download may take the user a while
+        encrypted_stream.set_read_timeout(None);
+
+        let result = share_store.transfer(&mut encrypted_stream, &session, medium, &None);
+        share_store.finish_download(result.is_ok());
+
+        if let Err(error) = result {
+            error!("Convenience download failed: {}", error);
         }
     }
 }
@@ -0,0 +1,395 @@
+use crate::get_tmp_dir;
+use crate::manifest::selected_entries;
+use crate::staging::{transfer_directory, STAGING_DIRECTORY};
+use log::{error, info, warn};
+use protocol::communication::file_manifest_entry::Kind;
+use protocol::communication::{FileManifestEntry, ResumePoint};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+pub(crate) const JOURNAL_DIRECTORY: &str = ".intershare";
+const JOURNAL_EXTENSION: &str = "journal";
+/// Interrupted transfers that were not continued for this long are removed, see `remove_stale_transfers`.
+const STALE_TRANSFER_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Identifies the offered content, so a journal is only reused if the files did not change in between.
+pub fn manifest_fingerprint(manifest: &[FileManifestEntry], selection: &[u32]) -> String {
+    let mut hasher = Sha256::new();
+
+    for entry in manifest {
+        hasher.update((entry.path.len() as u64).to_be_bytes());
+        hasher.update(entry.path.as_bytes());
+        hasher.update(entry.size.to_be_bytes());
+        hasher.update(entry.kind.to_be_bytes());
+        hasher.update(entry.modified_at.unwrap_or_default().to_be_bytes());
+    }
+
+    for index in selection {
+        hasher.update(index.to_be_bytes());
+    }
+
+    return hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+}
+
+/// Identifies the journal and staged files of a transfer. Transfer ids are chosen by the sender,
+/// so they are combined with its identity key, and another device can't continue or discard the transfer.
+/// Returns `None` if the transfer can't be resumed.
+pub(crate) fn resume_id(peer_identity_key: &[u8; 32], transfer_id: &str) -> Option<String> {
+    if transfer_id.is_empty() {
+        warn!("Empty transfer id. The transfer can't be resumed.");
+        return None;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(peer_identity_key);
+    hasher.update(transfer_id.as_bytes());
+
+    return Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    );
+}
+
+/// Receiver side record of the entries written so far, keyed by `resume_id`.
+/// It is appended to while unpacking, so it survives a dropped connection and lets
+/// the next attempt continue where the previous one stopped.
+pub(crate) struct TransferJournal {
+    file: Option<File>,
+    path: Option<PathBuf>,
+    entry_indices: HashMap<String, u32>,
+    roots: HashMap<OsString, PathBuf>,
+    started: HashMap<u32, PathBuf>,
+    completed: HashSet<u32>,
+    /// Entry index and byte offset the sender is asked to continue at
+    resume_from: Option<(u32, u64)>,
+}
+
+impl TransferJournal {
+    /// A journal that records nothing, used when the sender does not support resuming.
+    pub fn disabled(manifest: &[FileManifestEntry]) -> Self {
+        return Self {
+            file: None,
+            path: None,
+            entry_indices: entry_indices(manifest),
+            roots: HashMap::new(),
+            started: HashMap::new(),
+            completed: HashSet::new(),
+            resume_from: None,
+        };
+    }
+
+    /// Continues the journal of `resume_id`, if it belongs to the same manifest and selection.
+    /// Targets are only restored if they are inside `dest_dir` or `staging_root`.
+    pub fn open(
+        dest_dir: &Path,
+        staging_root: &Path,
+        resume_id: &str,
+        manifest: &[FileManifestEntry],
+        selection: &[u32],
+    ) -> Self {
+        // Next to the staging directory, so both are kept in the directory set with `set_tmp_dir`
+        let path = transfer_directory(dest_dir)
+            .join(resume_id)
+            .with_extension(JOURNAL_EXTENSION);
+
+        let fingerprint = manifest_fingerprint(manifest, selection);
+        let mut journal = Self::disabled(manifest);
+
+        if let Ok(content) = fs::read_to_string(&path) {
+            if content.lines().next() == Some(fingerprint.as_str())
+                && !journal.load(&content, dest_dir, staging_root)
+            {
+                warn!("Transfer journal points outside of the destination. Starting over.");
+                journal = Self::disabled(manifest);
+            }
+        }
+
+        let file = if journal.started.is_empty() {
+            fs::create_dir_all(path.parent().unwrap_or(dest_dir))
+                .and_then(|_| File::create(&path))
+                .and_then(|mut file| writeln!(file, "{}", fingerprint).map(|_| file))
+        } else {
+            OpenOptions::new().append(true).open(&path)
+        };
+
+        match file {
+            Ok(file) => journal.file = Some(file),
+            Err(error) => error!("Failed to open transfer journal: {}", error),
+        }
+
+        journal.path = Some(path);
+        journal.resume_from = journal.find_resume_point(manifest, selection);
+
+        return journal;
+    }
+
+    /// Returns `false` if a target is outside of where this transfer may write to.
+    fn load(&mut self, content: &str, dest_dir: &Path, staging_root: &Path) -> bool {
+        for line in content.lines().skip(1) {
+            let mut fields = line.split('\t');
+
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("root"), Some(root), Some(target)) => {
+                    if !is_below(dest_dir, Path::new(target)) {
+                        return false;
+                    }
+
+                    self.roots
+                        .insert(OsString::from(root), PathBuf::from(target));
+                }
+                (Some("started"), Some(index), Some(target)) => {
+                    if !is_below(staging_root, Path::new(target)) {
+                        return false;
+                    }
+
+                    if let Ok(index) = index.parse() {
+                        self.started.insert(index, PathBuf::from(target));
+                    }
+                }
+                (Some("completed"), Some(index), None) => {
+                    if let Ok(index) = index.parse() {
+                        self.completed.insert(index);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        return true;
+    }
+
+    fn find_resume_point(
+        &self,
+        manifest: &[FileManifestEntry],
+        selection: &[u32],
+    ) -> Option<(u32, u64)> {
+        if self.started.is_empty() {
+            return None;
+        }
+
+        let index = selected_entries(manifest, selection)
+            .into_iter()
+            .find(|index| !self.completed.contains(&(*index as u32)))?;
+
+        let entry = &manifest[index];
+        let offset = match (entry.kind(), self.started.get(&(index as u32))) {
+            (Kind::File, Some(target)) => fs::metadata(target)
+                .map(|metadata| metadata.len())
+                .ok()
+                .filter(|length| *length <= entry.size)
+                .unwrap_or(0),
+            _ => 0,
+        };
+
+        return Some((index as u32, offset));
+    }
+
+    pub fn resume_from(&self) -> Option<ResumePoint> {
+        return self
+            .resume_from
+            .map(|(entry, offset)| ResumePoint { entry, offset });
+    }
+
+    pub fn entry_index(&self, path: &str) -> Option<u32> {
+        return self.entry_indices.get(path).copied();
+    }
+
+    /// Target of the partially written entry the transfer continues with.
+    pub fn resumed_target(&self, index: u32) -> Option<&PathBuf> {
+        let (entry, offset) = self.resume_from?;
+
+        if entry != index || offset == 0 {
+            return None;
+        }
+
+        return self.started.get(&index);
+    }
+
+    /// Entries written by previous attempts, as their manifest path and target.
+    pub fn completed_targets(&self) -> Vec<(String, PathBuf)> {
+        let mut completed: Vec<(String, u32)> = self
+            .entry_indices
+            .iter()
+            .filter(|(_, index)| self.completed.contains(index))
+            .map(|(path, index)| (path.clone(), *index))
+            .collect();
+
+        completed.sort_by_key(|(_, index)| *index);
+
+        return completed
+            .into_iter()
+            .filter_map(|(path, index)| Some((path, self.started.get(&index)?.clone())))
+            .collect();
+    }
+
+    fn append(&mut self, line: String) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        if let Err(error) = writeln!(file, "{}", line) {
+            error!("Failed to write transfer journal: {}", error);
+        }
+    }
+
+    pub fn root_target(&self, root: &OsString) -> Option<&PathBuf> {
+        return self.roots.get(root);
+    }
+
+    pub fn record_root(&mut self, root: &OsString, target: &Path) {
+        self.roots.insert(root.clone(), target.to_path_buf());
+        self.append(format!(
+            "root\t{}\t{}",
+            root.to_string_lossy(),
+            target.to_string_lossy()
+        ));
+    }
+
+    pub fn record_started(&mut self, index: u32, target: &Path) {
+        self.started.insert(index, target.to_path_buf());
+        self.append(format!("started\t{}\t{}", index, target.to_string_lossy()));
+    }
+
+    pub fn record_completed(&mut self, index: u32) {
+        self.completed.insert(index);
+        self.append(format!("completed\t{}", index));
+    }
+
+    /// Whether the transfer can be continued by a later attempt.
+    pub fn is_resumable(&self) -> bool {
+        return self.file.is_some();
+    }
+
+    /// Removes the journal, once everything was received or the transfer won't be continued.
+    /// What was written is staged, see `Staging`.
+    pub fn finish(self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let _ = fs::remove_file(path);
+
+        // Only goes away if nothing else is journaled or staged in it, and never the tmp directory
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| parent.file_name() == Some(JOURNAL_DIRECTORY.as_ref()))
+        {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+}
+
+/// Removes journals and staged files of interrupted transfers that were not continued in time.
+/// Looks in `storage_dir`, the folders a `FolderLayout` creates in it, and the directory set with `set_tmp_dir`.
+pub(crate) fn remove_stale_transfers(storage_dir: &Path) {
+    let mut directories = vec![storage_dir.join(JOURNAL_DIRECTORY)];
+
+    if let Ok(entries) = fs::read_dir(storage_dir) {
+        directories.extend(
+            entries
+                .flatten()
+                .map(|entry| entry.path().join(JOURNAL_DIRECTORY)),
+        );
+    }
+
+    directories.extend(get_tmp_dir());
+
+    for directory in directories {
+        remove_stale_entries(&directory);
+    }
+}
+
+fn remove_stale_entries(directory: &Path) {
+    let staging_directory = directory.join(STAGING_DIRECTORY);
+
+    // Staged files are as old as their journal, which is written to whenever an entry was received
+    for staged in fs::read_dir(&staging_directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let journal = directory
+            .join(staged.file_name())
+            .with_extension(JOURNAL_EXTENSION);
+        let last_used = if journal.exists() {
+            journal
+        } else {
+            staged.path()
+        };
+
+        if is_stale(&last_used) {
+            info!("Removing stale staged files {:?}", staged.path());
+            let _ = fs::remove_dir_all(staged.path());
+        }
+    }
+
+    for entry in fs::read_dir(directory).into_iter().flatten().flatten() {
+        let path = entry.path();
+
+        if path.extension() == Some(JOURNAL_EXTENSION.as_ref()) && is_stale(&path) {
+            info!("Removing stale transfer journal {:?}", path);
+            let _ = fs::remove_file(&path);
+        }
+    }
+
+    // Only go away if they are empty, and never the directory set with `set_tmp_dir`
+    let _ = fs::remove_dir(&staging_directory);
+
+    if directory.file_name() == Some(JOURNAL_DIRECTORY.as_ref()) {
+        let _ = fs::remove_dir(directory);
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    return fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            modified
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed > STALE_TRANSFER_AGE)
+        });
+}
+
+/// Whether `path` is strictly inside `directory`, without leaving it through `..`.
+fn is_below(directory: &Path, path: &Path) -> bool {
+    return path.strip_prefix(directory).is_ok_and(|relative_path| {
+        relative_path.components().next().is_some()
+            && relative_path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+    });
+}
+
+fn entry_indices(manifest: &[FileManifestEntry]) -> HashMap<String, u32> {
+    return manifest
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.path.clone(), index as u32))
+        .collect();
+}
+
+/// Bytes left to transfer when continuing at `resume_from`.
+pub fn remaining_size(
+    manifest: &[FileManifestEntry],
+    selection: &[u32],
+    resume_from: Option<&ResumePoint>,
+) -> u64 {
+    return selected_entries(manifest, selection)
+        .into_iter()
+        .filter(|index| resume_from.is_none_or(|resume_from| *index as u32 >= resume_from.entry))
+        .map(|index| manifest[index].size)
+        .sum::<u64>()
+        .saturating_sub(resume_from.map_or(0, |resume_from| resume_from.offset));
+}
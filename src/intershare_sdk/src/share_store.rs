@@ -1,23 +1,53 @@
+use crate::access_code::{confirmation_matches, AccessCodeAttempts, AccessCodeExchange};
+use crate::clipboard::{clipboard_digest, representations, write_items, ClipboardItem};
+use crate::communication::Session;
+use crate::compression::compression_level;
+use crate::encryption::EncryptedReadWrite;
+use crate::identity::DeviceIdentity;
+use crate::integrity::verify_acknowledgement;
+use crate::manifest::{scan, scan_data, selected_entries, total_size, DataSource, ManifestSource};
 use crate::nearby_server::L2CapDelegate;
 use crate::tar::stream_tar;
+use crate::trust_store::{trust_store, TrustLevel};
 use crate::{
     connection::Connection, convert_os_str, encryption::generate_secure_base64_token,
     errors::ConnectErrors,
 };
 use fast_qr::convert::{image::ImageBuilder, Builder, Shape};
 use fast_qr::qr::QRBuilder;
-use log::{error, info};
+use log::{error, info, warn};
 use prost_stream::Stream;
 use protocol::{
     communication::{
+        capabilities::{Compression, IntentType},
+        convenience_download_response::Status,
         request::{Intent, RequestTypes},
-        ClipboardTransferIntent, FileTransferIntent, Request, TransferRequestResponse,
+        AccessCodeConfirmation, ClipboardTransferIntent, ConvenienceDownloadResponse,
+        FileManifestEntry, FileTransferIntent, Request, TransferAcknowledgement,
+        TransferCancellation, TransferRequestResponse,
     },
     discovery::{Device, DeviceConnectionInfo},
+    prost::Message,
 };
-use std::{fmt::Debug, fs::File, path::Path, sync::Arc};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt::Debug, path::Path, sync::Arc};
+use tempfile::NamedTempFile;
 use tokio::sync::RwLock;
+use uuid::Uuid;
 
+/// How long the sender waits for the receiver to acknowledge a transfer, once everything was sent.
+/// Receivers may still verify and move large files in that time.
+const ACKNOWLEDGEMENT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the files linked from a share's page can be downloaded, after the page was opened.
+const HTTP_SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ConnectionMedium {
     BLE,
     WiFi,
@@ -27,26 +57,76 @@ pub enum SendProgressState {
     Unknown,
     Connecting,
     Requesting,
-    ConnectionMediumUpdate { medium: ConnectionMedium },
-    Transferring { progress: f64 },
+    ConnectionMediumUpdate {
+        medium: ConnectionMedium,
+    },
+    Verification {
+        sas: String,
+    },
+    Transferring {
+        progress: f64,
+    },
     Cancelled,
+    /// Only reported once the receiver acknowledged the transfer.
     Finished,
     Declined,
+    Failed,
 }
 
 pub trait SendProgressDelegate: Send + Sync + Debug {
     fn progress_changed(&self, progress: SendProgressState);
 }
 
+/// Restrictions for downloading a share via its convenience link.
+#[derive(Clone, Default, Debug)]
+pub struct SharePolicy {
+    /// Unix timestamp in seconds, after which the link can no longer be used.
+    pub expires_at: Option<u64>,
+    /// In browsers, opening the share's page counts as one download, including the files it links to.
+    pub max_downloads: Option<u32>,
+    /// Device ids allowed to download. A listed device has to present the identity key
+    /// pinned for its id, so devices that were never paired are refused.
+    pub allowed_device_ids: Option<Vec<String>>,
+}
+
 pub struct ShareStore {
     pub request_id: String,
     pub file_paths: Option<Vec<String>>,
     pub clipboard: Option<String>,
+    /// Representations of the clipboard content besides the plain text
+    clipboard_items: Vec<ClipboardItem>,
+    /// Shared in addition to `file_paths`, without being files on disk
+    data_sources: Vec<DataSource>,
     allow_convenience_share: bool,
+    access_code: Option<String>,
+    access_code_attempts: Mutex<AccessCodeAttempts>,
+    policy: Mutex<SharePolicy>,
+    download_count: AtomicU32,
+    /// Convenience downloads that were counted, but did not finish yet
+    downloads_in_progress: AtomicU32,
+    /// Browser sessions, with the time their page was opened
+    http_sessions: Mutex<HashMap<String, Instant>>,
+    /// Archives served over HTTP, keyed by the index of the shared folder or `None` for the whole share
+    http_archives: Mutex<HashMap<Option<usize>, NamedTempFile>>,
+    http_port: AtomicU16,
+    should_cancel: AtomicBool,
+    cancel_reason: Mutex<Option<String>>,
     ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>,
+    identity: Arc<DeviceIdentity>,
     device_connection_info: DeviceConnectionInfo,
 }
 
+fn send_download_status<T>(stream: &mut T, status: Status)
+where
+    T: Read + Write,
+{
+    let _ = Stream::new(stream).send(&ConvenienceDownloadResponse {
+        status: status as i32,
+        access_code_message: None,
+        access_code_confirmation: None,
+    });
+}
+
 pub(crate) fn update_progress(
     progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
     state: SendProgressState,
@@ -62,53 +142,223 @@ impl ShareStore {
         file_paths: Option<Vec<String>>,
         clipboard: Option<String>,
         allow_convenience_share: bool,
+        access_code: Option<String>,
         ble_l2_cap_client: Arc<RwLock<Option<Box<dyn L2CapDelegate>>>>,
+        identity: Arc<DeviceIdentity>,
         device_connection_info: DeviceConnectionInfo,
     ) -> Self {
         Self {
             request_id: generate_secure_base64_token(23),
             file_paths,
             clipboard,
+            clipboard_items: vec![],
+            data_sources: vec![],
             allow_convenience_share,
+            access_code: access_code.filter(|code| !code.trim().is_empty()),
+            access_code_attempts: Mutex::new(AccessCodeAttempts::default()),
+            policy: Mutex::new(SharePolicy::default()),
+            download_count: AtomicU32::new(0),
+            downloads_in_progress: AtomicU32::new(0),
+            http_sessions: Mutex::new(HashMap::new()),
+            http_archives: Mutex::new(HashMap::new()),
+            http_port: AtomicU16::new(0),
+            should_cancel: AtomicBool::new(false),
+            cancel_reason: Mutex::new(None),
             ble_l2_cap_client,
+            identity,
             device_connection_info,
         }
     }
 
+    /// Adds representations of the clipboard content besides the plain text.
+    pub(crate) fn with_clipboard_items(mut self, clipboard_items: Vec<ClipboardItem>) -> Self {
+        self.clipboard_items = clipboard_items;
+        return self;
+    }
+
+    /// Adds content that is not read from the file system. Such shares are not served over HTTP.
+    pub(crate) fn with_data_sources(mut self, data_sources: Vec<DataSource>) -> Self {
+        self.data_sources = data_sources;
+        return self;
+    }
+
     pub async fn send_to(
         &self,
         receiver: Device,
         progress_delegate: Option<Box<dyn SendProgressDelegate>>,
     ) -> Result<(), ConnectErrors> {
+        if self.file_paths.is_none() && self.clipboard.is_none() {
+            return Err(ConnectErrors::NoTextProvided);
+        }
+
+        update_progress(&progress_delegate, SendProgressState::Connecting);
+
+        let connection = Connection::new(self.ble_l2_cap_client.clone(), self.identity.clone());
+
+        let (mut encrypted_stream, session, medium) = connection
+            .connect(receiver, &progress_delegate)
+            .await
+            .inspect_err(|_| update_progress(&progress_delegate, SendProgressState::Unknown))?;
+
+        if self.is_cancelled() {
+            return Err(self.abort_transfer(&mut encrypted_stream, &progress_delegate));
+        }
+
+        update_progress(
+            &progress_delegate,
+            SendProgressState::Verification {
+                sas: session.short_authentication_string(),
+            },
+        );
+
+        return self.transfer(&mut encrypted_stream, &session, medium, &progress_delegate);
+    }
+
+    pub fn get_request_id(&self) -> String {
+        return self.request_id.clone();
+    }
+
+    pub fn allows_convenience_share(&self) -> bool {
+        return self.allow_convenience_share;
+    }
+
+    pub fn get_policy(&self) -> SharePolicy {
+        return self.policy.lock().unwrap().clone();
+    }
+
+    pub fn set_policy(&self, policy: SharePolicy) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    pub fn get_download_count(&self) -> u32 {
+        return self.download_count.load(Ordering::SeqCst);
+    }
+
+    /// Stops the transfers currently running for this share, and refuses later ones.
+    /// The receiver is told the `reason` and removes what it received of the unfinished files.
+    /// To share the same data again, create a new share.
+    pub fn cancel(&self, reason: Option<String>) {
+        *self.cancel_reason.lock().unwrap() = reason;
+        self.should_cancel.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        return self.should_cancel.load(Ordering::Relaxed);
+    }
+
+    /// Why the share can no longer be downloaded via its link, or `None` while it can.
+    /// A share exhausted by browsers stays available while their sessions may still fetch files,
+    /// and so does one exhausted by convenience downloads that may still fail.
+    pub(crate) fn ended_status(&self) -> Option<Status> {
+        if self.is_cancelled() {
+            return Some(Status::Revoked);
+        }
+
+        let (expired, max_downloads) = {
+            let policy = self.policy.lock().unwrap();
+            (is_expired(&policy), policy.max_downloads)
+        };
+
+        if expired {
+            return Some(Status::Expired);
+        }
+
+        let exhausted = max_downloads
+            .is_some_and(|max_downloads| self.get_download_count() >= max_downloads);
+
+        let in_progress = self.downloads_in_progress.load(Ordering::SeqCst) > 0;
+
+        if exhausted && !in_progress && self.live_http_sessions().is_empty() {
+            return Some(Status::Exhausted);
+        }
+
+        return None;
+    }
+
+    /// Ends the stream with an abort record, so the receiver can tell a cancelled transfer
+    /// from a dropped connection.
+    fn abort_transfer(
+        &self,
+        encrypted_stream: &mut Box<dyn EncryptedReadWrite>,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
+    ) -> ConnectErrors {
+        let cancellation = TransferCancellation {
+            reason: self.cancel_reason.lock().unwrap().clone(),
+        };
+
+        info!("Cancelling transfer: {:?}", cancellation.reason);
+
+        if let Err(error) = encrypted_stream.abort(&cancellation.encode_to_vec()) {
+            warn!(
+                "Failed to notify the receiver about the cancellation: {}",
+                error
+            );
+        }
+
+        encrypted_stream.close();
+        update_progress(progress_delegate, SendProgressState::Cancelled);
+
+        return ConnectErrors::Cancelled;
+    }
+
+    /// Sends the shared content over an already established connection.
+    /// Used for pushing to a receiver, as well as answering convenience downloads.
+    pub(crate) fn transfer(
+        &self,
+        encrypted_stream: &mut Box<dyn EncryptedReadWrite>,
+        session: &Session,
+        medium: ConnectionMedium,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        let intent = if self.file_paths.is_some() {
+            IntentType::FileTransfer
+        } else if self.clipboard_items.is_empty() {
+            IntentType::Clipboard
+        } else {
+            IntentType::RichClipboard
+        };
+
+        // Receivers without support for rich clipboard content still get the plain text.
+        let has_text = self.clipboard.as_ref().is_some_and(|text| !text.is_empty());
+        let falls_back_to_text = intent == IntentType::RichClipboard
+            && has_text
+            && session.capabilities.supports_intent(IntentType::Clipboard);
+
+        if !session.capabilities.supports_intent(intent) && !falls_back_to_text {
+            update_progress(progress_delegate, SendProgressState::Unknown);
+            return Err(ConnectErrors::UnsupportedIntent);
+        }
+
         return if self.file_paths.is_none() {
-            self.send_text(receiver, progress_delegate).await
+            self.send_text(encrypted_stream, session, progress_delegate)
         } else {
-            self.send_files(receiver, progress_delegate).await
+            self.send_files(encrypted_stream, session, medium, progress_delegate)
         };
     }
 
-    async fn send_text(
+    fn send_text(
         &self,
-        receiver: Device,
-        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+        encrypted_stream: &mut Box<dyn EncryptedReadWrite>,
+        session: &Session,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
     ) -> Result<(), ConnectErrors> {
         let Some(text) = &self.clipboard else {
             return Err(ConnectErrors::NoTextProvided);
         };
 
-        update_progress(&progress_delegate, SendProgressState::Connecting);
-
-        let connection = Connection::new(self.ble_l2_cap_client.clone());
-
-        let mut encrypted_stream = connection
-            .connect(receiver, &progress_delegate)
-            .await
-            .inspect_err(|_| update_progress(&progress_delegate, SendProgressState::Unknown))?;
+        let items: &[ClipboardItem] = if session
+            .capabilities
+            .supports_intent(IntentType::RichClipboard)
+        {
+            &self.clipboard_items
+        } else {
+            &[]
+        };
 
-        let mut proto_stream = Stream::new(&mut encrypted_stream);
+        let mut proto_stream = Stream::new(&mut *encrypted_stream);
 
         update_progress(
-            &progress_delegate,
+            progress_delegate,
             SendProgressState::Transferring { progress: 0.0 },
         );
 
@@ -116,42 +366,98 @@ impl ShareStore {
             r#type: RequestTypes::ShareRequest as i32,
             device: self.device_connection_info.device.clone(),
             share_id: None,
+            access_code_message: None,
             intent: Some(Intent::Clipboard(ClipboardTransferIntent {
                 clipboard_content: text.to_string(),
+                representations: representations(items),
             })),
         };
 
         update_progress(
-            &progress_delegate,
+            progress_delegate,
             SendProgressState::Transferring { progress: 0.8 },
         );
         let _ = proto_stream.send(&transfer_request);
-        update_progress(&progress_delegate, SendProgressState::Finished);
+
+        let response = match proto_stream.recv::<TransferRequestResponse>() {
+            Ok(response) => response,
+            Err(error) => {
+                update_progress(progress_delegate, SendProgressState::Failed);
+
+                if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                    return Err(integrity_error.into());
+                }
+
+                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                    error: error.to_string(),
+                });
+            }
+        };
+
+        if !response.accepted {
+            update_progress(progress_delegate, SendProgressState::Declined);
+            return Err(ConnectErrors::Declined);
+        }
+
+        if let Err(error) = write_items(&mut *encrypted_stream, items) {
+            update_progress(progress_delegate, SendProgressState::Failed);
+
+            if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                return Err(integrity_error.into());
+            }
+
+            return Err(ConnectErrors::FailedToSendClipboard {
+                error: error.to_string(),
+            });
+        }
+
+        encrypted_stream.set_read_timeout(Some(ACKNOWLEDGEMENT_TIMEOUT));
+        let mut proto_stream = Stream::new(&mut *encrypted_stream);
+
+        let acknowledgement = match proto_stream.recv::<TransferAcknowledgement>() {
+            Ok(acknowledgement) => acknowledgement,
+            Err(error) => {
+                update_progress(progress_delegate, SendProgressState::Failed);
+
+                if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                    return Err(integrity_error.into());
+                }
+
+                return Err(ConnectErrors::FailedToGetTransferAcknowledgement {
+                    error: error.to_string(),
+                });
+            }
+        };
+
+        if !verify_acknowledgement(&acknowledgement, session, &clipboard_digest(text, items)) {
+            update_progress(progress_delegate, SendProgressState::Failed);
+            return Err(ConnectErrors::InvalidAcknowledgement);
+        }
+
+        if !acknowledgement.verified {
+            update_progress(progress_delegate, SendProgressState::Failed);
+            return Err(ConnectErrors::ReceiverFailed);
+        }
+
+        update_progress(progress_delegate, SendProgressState::Finished);
 
         return Ok(());
     }
 
-    async fn send_files(
+    fn send_files(
         &self,
-        receiver: Device,
-        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+        encrypted_stream: &mut Box<dyn EncryptedReadWrite>,
+        session: &Session,
+        medium: ConnectionMedium,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
     ) -> Result<(), ConnectErrors> {
         let Some(file_paths) = &self.file_paths else {
             return Err(ConnectErrors::NoFilesProvided);
         };
 
-        update_progress(&progress_delegate, SendProgressState::Connecting);
+        let mut proto_stream = Stream::new(&mut *encrypted_stream);
 
-        let connection = Connection::new(self.ble_l2_cap_client.clone());
-
-        let mut encrypted_stream = connection
-            .connect(receiver, &progress_delegate)
-            .await
-            .inspect_err(|_| update_progress(&progress_delegate, SendProgressState::Unknown))?;
-
-        let mut proto_stream = Stream::new(&mut encrypted_stream);
-
-        update_progress(&progress_delegate, SendProgressState::Requesting);
+        update_progress(progress_delegate, SendProgressState::Requesting);
 
         let file_name = file_paths.first().map(|file_path| {
             convert_os_str(
@@ -161,61 +467,196 @@ impl ShareStore {
             )
         });
 
-        let mut file_size: u64 = 0;
+        let mut sources = scan(file_paths).map_err(|error| {
+            update_progress(progress_delegate, SendProgressState::Unknown);
+            ConnectErrors::FailedToDetermineFileSize {
+                error: error.to_string(),
+            }
+        })?;
+        sources.extend(scan_data(&self.data_sources));
 
-        for file_path in file_paths {
-            let metadata = File::open(file_path)
-                .expect("Failed to read file")
-                .metadata()
-                .expect("Failed to get file metadata");
+        let file_name =
+            file_name.or_else(|| sources.first().map(|source| source.entry.path.clone()));
+        let file_count = file_paths.len() + self.data_sources.len();
 
-            file_size = file_size + metadata.len();
-        }
+        let manifest: Vec<FileManifestEntry> =
+            sources.iter().map(|source| source.entry.clone()).collect();
+        let file_size = total_size(&manifest);
 
         info!("Total size of files: {}", file_size);
 
+        let offered_compression = match session.capabilities.compression {
+            Compression::Zstd => vec![Compression::Zstd as i32],
+            Compression::None => vec![],
+        };
+
         let transfer_request = Request {
             r#type: RequestTypes::ShareRequest as i32,
             device: self.device_connection_info.device.clone(),
             share_id: None,
+            access_code_message: None,
             intent: Some(Intent::FileTransfer(FileTransferIntent {
                 file_name,
                 file_size,
-                file_count: file_paths.len() as u64,
+                file_count: file_count as u64,
+                manifest: manifest.clone(),
+                transfer_id: Some(self.request_id.clone()),
+                compression: offered_compression.clone(),
             })),
         };
 
         let _ = proto_stream.send(&transfer_request);
 
-        let response = proto_stream
-            .recv::<TransferRequestResponse>()
-            .map_err(|error| ConnectErrors::FailedToGetTransferRequestResponse {
-                error: error.to_string(),
-            })?;
+        let response = match proto_stream.recv::<TransferRequestResponse>() {
+            Ok(response) => response,
+            Err(error) => {
+                update_progress(progress_delegate, SendProgressState::Failed);
+
+                if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                    return Err(integrity_error.into());
+                }
+
+                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                    error: error.to_string(),
+                });
+            }
+        };
 
         if !response.accepted {
-            update_progress(&progress_delegate, SendProgressState::Declined);
+            update_progress(progress_delegate, SendProgressState::Declined);
             return Err(ConnectErrors::Declined);
         }
 
+        let compression_level = match response.compression() {
+            Compression::None => None,
+            compression if offered_compression.contains(&(compression as i32)) => {
+                Some(compression_level(&medium))
+            }
+            _ => {
+                update_progress(progress_delegate, SendProgressState::Unknown);
+                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                    error: "The receiver picked a compression that was not offered".to_string(),
+                });
+            }
+        };
+
+        let resume_from = response.resume_from.filter(|_| session.capabilities.resume);
+
+        if let Some(resume_from) = &resume_from {
+            info!(
+                "Resuming transfer at entry {}, offset {}",
+                resume_from.entry, resume_from.offset
+            );
+        }
+
+        // Entries before the resume point were completed by a previous attempt
+        let (selected_indices, completed_indices): (Vec<usize>, Vec<usize>) =
+            selected_entries(&manifest, &response.selected_entries)
+                .into_iter()
+                .partition(|index| {
+                    resume_from
+                        .as_ref()
+                        .is_none_or(|resume_from| *index as u32 >= resume_from.entry)
+                });
+
+        let selected_sources: Vec<&ManifestSource> = selected_indices
+            .into_iter()
+            .map(|index| &sources[index])
+            .collect();
+        let completed_sources: Vec<&ManifestSource> = completed_indices
+            .into_iter()
+            .map(|index| &sources[index])
+            .collect();
+
+        let resume_offset = match (&resume_from, selected_sources.first()) {
+            (Some(resume_from), Some(source))
+                if manifest.get(resume_from.entry as usize) == Some(&source.entry) =>
+            {
+                resume_from.offset
+            }
+            _ => 0,
+        };
+
+        let selected_size = total_size(selected_sources.iter().map(|source| &source.entry))
+            .saturating_sub(resume_offset);
+
+        if self.is_cancelled() {
+            return Err(self.abort_transfer(encrypted_stream, progress_delegate));
+        }
+
         update_progress(
-            &progress_delegate,
+            progress_delegate,
             SendProgressState::Transferring { progress: 0.0 },
         );
 
         let tar_result = stream_tar(
-            &mut encrypted_stream,
-            file_paths,
-            file_size,
-            &progress_delegate,
+            encrypted_stream,
+            &selected_sources,
+            &completed_sources,
+            resume_offset,
+            compression_level,
+            selected_size,
+            progress_delegate,
+            &self.should_cancel,
         );
 
-        if let Err(error) = tar_result {
-            error!("Error while tarring: {}", error);
-            update_progress(&progress_delegate, SendProgressState::Cancelled);
+        let digests = match tar_result {
+            Ok(digests) => digests,
+            Err(_) if self.is_cancelled() => {
+                return Err(self.abort_transfer(encrypted_stream, progress_delegate));
+            }
+            Err(error) => {
+                error!("Error while tarring: {}", error);
+                update_progress(progress_delegate, SendProgressState::Failed);
+
+                if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                    return Err(integrity_error.into());
+                }
+
+                return Err(ConnectErrors::FailedToSendFiles {
+                    error: error.to_string(),
+                });
+            }
+        };
+
+        // Everything was sent, but only the receiver knows whether it was extracted successfully.
+        encrypted_stream.set_read_timeout(Some(ACKNOWLEDGEMENT_TIMEOUT));
+        let acknowledgement =
+            match Stream::new(&mut *encrypted_stream).recv::<TransferAcknowledgement>() {
+                Ok(acknowledgement) => acknowledgement,
+                Err(error) => {
+                    update_progress(progress_delegate, SendProgressState::Failed);
+
+                    if let Some(integrity_error) = encrypted_stream.integrity_error() {
+                        return Err(integrity_error.into());
+                    }
+
+                    return Err(ConnectErrors::FailedToGetTransferAcknowledgement {
+                        error: error.to_string(),
+                    });
+                }
+            };
+
+        if !verify_acknowledgement(&acknowledgement, session, &digests) {
+            update_progress(progress_delegate, SendProgressState::Failed);
+            return Err(ConnectErrors::InvalidAcknowledgement);
         }
 
-        update_progress(&progress_delegate, SendProgressState::Finished);
+        if !acknowledgement.verified {
+            update_progress(progress_delegate, SendProgressState::Failed);
+
+            if acknowledgement.failed_files.is_empty() {
+                return Err(ConnectErrors::ReceiverFailed);
+            }
+
+            error!(
+                "Receiver reported corrupted files: {:?}",
+                acknowledgement.failed_files
+            );
+            return Err(ConnectErrors::IntegrityCheckFailed);
+        }
+
+        update_progress(progress_delegate, SendProgressState::Finished);
 
         return Ok(());
     }
@@ -237,36 +678,381 @@ impl ShareStore {
         let ip = tcp_connection_info.hostname;
         let port = tcp_connection_info.port;
 
-        let link = format!(
+        let mut link = format!(
             "https://s.intershare.app?i={0}&ip={1}&p={2}&d={3}",
             self.request_id, ip, port, device.id
         );
 
+        // The code itself is never part of the link, only the hint to ask for it.
+        if self.access_code.is_some() {
+            link.push_str("&c=1");
+        }
+
         return Some(link);
     }
 
-    pub fn generate_qr_code(&self, dark_mode: bool) -> Option<Vec<u8>> {
-        let link = self.generate_link()?;
-
-        let qrcode = QRBuilder::new(link).build().unwrap();
-
-        let img = ImageBuilder::default()
-            .shape(Shape::Circle)
-            .module_color(if dark_mode {
-                [255, 255, 255, 255]
-            } else {
-                [0, 0, 0, 255]
-            })
-            .background_color([0, 0, 0, 0])
-            .fit_width(300)
-            .to_bytes(&qrcode);
-
-        img.inspect_err(|error_message| {
-            error!(
-                "Error while trying to generate QR code: {:?}",
-                error_message
+    /// Answers a convenience download request for this share. The request has to satisfy the
+    /// share policy, and if an access code is set, the downloader has to prove knowledge of it
+    /// before anything is transferred. Once authorized, the download has to be ended with
+    /// `finish_download`.
+    pub(crate) fn authorize_download<T>(
+        &self,
+        request: &Request,
+        stream: &mut T,
+        session: &Session,
+    ) -> bool
+    where
+        T: Read + Write,
+    {
+        if !self.allow_convenience_share || request.share_id() != self.request_id {
+            warn!(
+                "Received convenience download request with an unknown share id: {:?}",
+                request.share_id
+            );
+
+            send_download_status(stream, Status::NotFound);
+            return false;
+        }
+
+        if let Err(status) = self.check_policy(request, session) {
+            warn!(
+                "Convenience download request violates the share policy: {:?}",
+                status
+            );
+
+            send_download_status(stream, status);
+            return false;
+        }
+
+        if !self.start_download() {
+            send_download_status(stream, Status::Exhausted);
+            return false;
+        }
+
+        let authorized = self.verify_access_code(request, stream, session);
+
+        if !authorized {
+            self.finish_download(false);
+        }
+
+        return authorized;
+    }
+
+    fn start_download(&self) -> bool {
+        // Counted first, so the share isn't removed as exhausted while the download may still fail
+        self.downloads_in_progress.fetch_add(1, Ordering::SeqCst);
+
+        if self.reserve_download() {
+            return true;
+        }
+
+        self.downloads_in_progress.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+
+    /// Ends a download authorized by `authorize_download`. A download that didn't complete
+    /// is given back, so it doesn't count against `max_downloads`.
+    pub(crate) fn finish_download(&self, completed: bool) {
+        if !completed {
+            self.download_count.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        self.downloads_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn check_policy(&self, request: &Request, session: &Session) -> Result<(), Status> {
+        let policy = self.policy.lock().unwrap();
+
+        if is_expired(&policy) {
+            return Err(Status::Expired);
+        }
+
+        if let Some(allowed_device_ids) = &policy.allowed_device_ids {
+            let Some(device) = &request.device else {
+                return Err(Status::DeviceNotAllowed);
+            };
+
+            if !allowed_device_ids.contains(&device.id) {
+                return Err(Status::DeviceNotAllowed);
+            }
+
+            // The device id is only a claim, the pinned identity key makes sure it's the same device.
+            let trust_level = trust_store()
+                .read()
+                .unwrap()
+                .check(&device.id, &session.peer_identity_key);
+
+            if trust_level != TrustLevel::Verified {
+                return Err(Status::DeviceNotAllowed);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Browsers can neither run the access code exchange nor prove a device identity,
+    /// so only shares without those restrictions are served over HTTP.
+    pub(crate) fn check_http_access(&self) -> Result<(), Status> {
+        if !self.allow_convenience_share || !self.data_sources.is_empty() {
+            return Err(Status::NotFound);
+        }
+
+        if self.access_code.is_some() {
+            return Err(Status::AccessCodeRequired);
+        }
+
+        let policy = self.policy.lock().unwrap();
+
+        if is_expired(&policy) {
+            return Err(Status::Expired);
+        }
+
+        if policy.allowed_device_ids.is_some() {
+            return Err(Status::DeviceNotAllowed);
+        }
+
+        return Ok(());
+    }
+
+    /// Counts the download against `max_downloads`. Returns `false` if no downloads are left.
+    pub(crate) fn reserve_download(&self) -> bool {
+        let max_downloads = self.policy.lock().unwrap().max_downloads;
+
+        return self
+            .download_count
+            .fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |count| match max_downloads {
+                    Some(max_downloads) if count >= max_downloads => None,
+                    _ => Some(count + 1),
+                },
             )
-        })
-        .ok()
+            .is_ok();
     }
+
+    /// A browser session starts with the share's page and counts as one download, no matter how
+    /// many of the linked files it fetches. Returns its token, or `None` if no downloads are left.
+    pub(crate) fn start_http_session(&self) -> Option<String> {
+        if !self.reserve_download() {
+            return None;
+        }
+
+        let token = Uuid::new_v4().to_string();
+
+        // Nothing is counted without a limit, so the token isn't needed later on
+        if self.policy.lock().unwrap().max_downloads.is_some() {
+            self.live_http_sessions().insert(token.clone(), Instant::now());
+        }
+
+        return Some(token);
+    }
+
+    /// Counts a file or archive download, unless it was requested from a session's page.
+    pub(crate) fn reserve_http_download(&self, session: Option<&str>) -> bool {
+        if session.is_some_and(|session| self.live_http_sessions().contains_key(session)) {
+            return true;
+        }
+
+        return self.reserve_download();
+    }
+
+    /// Drops the sessions older than `HTTP_SESSION_LIFETIME` and returns the remaining ones.
+    fn live_http_sessions(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        let mut sessions = self.http_sessions.lock().unwrap();
+        sessions.retain(|_, started_at| started_at.elapsed() < HTTP_SESSION_LIFETIME);
+
+        return sessions;
+    }
+
+    /// Returns a handle to the archive for `index`, see `http_archives`.
+    /// The archive is built on the first request and reused for later downloads of the share.
+    pub(crate) fn http_archive(
+        &self,
+        index: Option<usize>,
+        build: impl FnOnce() -> io::Result<NamedTempFile>,
+    ) -> io::Result<File> {
+        let mut archives = self.http_archives.lock().unwrap();
+
+        if !archives.contains_key(&index) {
+            archives.insert(index, build()?);
+        }
+
+        // Every handle has its own position, so downloads can run concurrently
+        return archives[&index].reopen();
+    }
+
+    /// Every completed exchange tells the downloader whether its guess was right, so wrong
+    /// guesses lock the share for a while, see `AccessCodeAttempts`.
+    fn verify_access_code<T>(&self, request: &Request, stream: &mut T, session: &Session) -> bool
+    where
+        T: Read + Write,
+    {
+        let Some(access_code) = &self.access_code else {
+            send_download_status(stream, Status::Accepted);
+            return true;
+        };
+
+        let Some(foreign_message) = &request.access_code_message else {
+            send_download_status(stream, Status::AccessCodeRequired);
+            return false;
+        };
+
+        if !self
+            .access_code_attempts
+            .lock()
+            .unwrap()
+            .reserve(Instant::now())
+        {
+            warn!("Rejecting access code attempt, the share is locked");
+            send_download_status(stream, Status::Locked);
+            return false;
+        }
+
+        let outcome = exchange_access_code(access_code, foreign_message, stream, session);
+        let mut attempts = self.access_code_attempts.lock().unwrap();
+
+        return match outcome {
+            Some(true) => {
+                attempts.succeeded();
+                true
+            }
+            Some(false) => {
+                attempts.failed(Instant::now());
+                false
+            }
+            None => {
+                attempts.abandoned();
+                false
+            }
+        };
+    }
+
+    pub(crate) fn set_http_port(&self, port: Option<u16>) {
+        self.http_port.store(port.unwrap_or(0), Ordering::SeqCst);
+    }
+
+    pub(crate) fn get_sender_name(&self) -> Option<String> {
+        return self
+            .device_connection_info
+            .device
+            .as_ref()
+            .map(|device| device.name.clone());
+    }
+
+    /// Plain http link for browsers without the app installed.
+    /// Only available while the HTTP server is running, and if the share can be served over HTTP.
+    pub fn generate_http_link(&self) -> Option<String> {
+        let port = self.http_port.load(Ordering::SeqCst);
+
+        if port == 0 || self.check_http_access().is_err() {
+            return None;
+        }
+
+        let tcp_connection_info = self.device_connection_info.tcp.as_ref()?;
+
+        return Some(format!(
+            "http://{0}:{1}/{2}",
+            tcp_connection_info.hostname, port, self.request_id
+        ));
+    }
+
+    pub fn generate_qr_code(&self, dark_mode: bool) -> Option<Vec<u8>> {
+        return generate_qr_code(self.generate_link()?, dark_mode);
+    }
+
+    pub fn generate_http_qr_code(&self, dark_mode: bool) -> Option<Vec<u8>> {
+        return generate_qr_code(self.generate_http_link()?, dark_mode);
+    }
+}
+
+/// Runs the SPAKE2 exchange for a download request. Returns whether both sides used the same code,
+/// or `None` if the downloader never confirmed its code.
+/// The downloader has to confirm first, so it learns nothing about the code by leaving early.
+fn exchange_access_code<T>(
+    access_code: &str,
+    foreign_message: &[u8],
+    stream: &mut T,
+    session: &Session,
+) -> Option<bool>
+where
+    T: Read + Write,
+{
+    let mut proto_stream = Stream::new(stream);
+
+    let (exchange, message) = AccessCodeExchange::start(access_code, session);
+
+    let Some(confirmations) = exchange.finish(foreign_message) else {
+        warn!("Received a malformed access code message");
+        return None;
+    };
+
+    let _ = proto_stream.send(&ConvenienceDownloadResponse {
+        status: Status::Accepted as i32,
+        access_code_message: Some(message),
+        access_code_confirmation: None,
+    });
+
+    let Ok(confirmation) = proto_stream.recv::<AccessCodeConfirmation>() else {
+        warn!("Downloader did not confirm the access code");
+        return None;
+    };
+
+    if !confirmation_matches(
+        &confirmations.downloader,
+        &confirmation.access_code_confirmation,
+    ) {
+        warn!("Downloader used a wrong access code");
+        let _ = proto_stream.send(&ConvenienceDownloadResponse {
+            status: Status::InvalidAccessCode as i32,
+            access_code_message: None,
+            access_code_confirmation: None,
+        });
+
+        return Some(false);
+    }
+
+    let _ = proto_stream.send(&ConvenienceDownloadResponse {
+        status: Status::Accepted as i32,
+        access_code_message: None,
+        access_code_confirmation: Some(confirmations.sharer.to_vec()),
+    });
+
+    return Some(true);
+}
+
+fn is_expired(policy: &SharePolicy) -> bool {
+    let Some(expires_at) = policy.expires_at else {
+        return false;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(u64::MAX);
+
+    return now >= expires_at;
+}
+
+fn generate_qr_code(link: String, dark_mode: bool) -> Option<Vec<u8>> {
+    let qrcode = QRBuilder::new(link).build().unwrap();
+
+    let img = ImageBuilder::default()
+        .shape(Shape::Circle)
+        .module_color(if dark_mode {
+            [255, 255, 255, 255]
+        } else {
+            [0, 0, 0, 255]
+        })
+        .background_color([0, 0, 0, 0])
+        .fit_width(300)
+        .to_bytes(&qrcode);
+
+    img.inspect_err(|error_message| {
+        error!(
+            "Error while trying to generate QR code: {:?}",
+            error_message
+        )
+    })
+    .ok()
 }
@@ -0,0 +1,144 @@
+use crate::get_tmp_dir;
+use crate::resume::JOURNAL_DIRECTORY;
+use log::{error, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+pub(crate) const STAGING_DIRECTORY: &str = "staging";
+
+/// Directory received entries are written to before they are moved into the destination.
+/// It mirrors the destination, so every final path has exactly one staged path and back.
+/// A cancelled or failed transfer therefore never leaves partial files in the destination.
+pub(crate) struct Staging {
+    destination: PathBuf,
+    root: PathBuf,
+}
+
+impl Staging {
+    /// Resumable transfers are staged below their transfer id, so the next attempt finds
+    /// what was written so far. Others get a fresh directory, see `transfer_directory`.
+    pub fn open(destination: &Path, transfer_id: Option<&str>, resume: bool) -> io::Result<Self> {
+        let id = match transfer_id {
+            Some(transfer_id) => transfer_id.to_string(),
+            None => Uuid::new_v4().to_string(),
+        };
+
+        let root = Self::root(destination, &id);
+
+        // Left over from an attempt that can't be continued
+        if !resume && root.exists() {
+            fs::remove_dir_all(&root)?;
+        }
+
+        fs::create_dir_all(&root)?;
+
+        return Ok(Self {
+            destination: destination.to_path_buf(),
+            root,
+        });
+    }
+
+    /// Directory the transfer `id` is staged in.
+    pub fn root(destination: &Path, id: &str) -> PathBuf {
+        return transfer_directory(destination)
+            .join(STAGING_DIRECTORY)
+            .join(id);
+    }
+
+    pub fn destination(&self) -> &Path {
+        return &self.destination;
+    }
+
+    /// Where the entry that ends up at `final_path` is written to.
+    pub fn staged_path(&self, final_path: &Path) -> PathBuf {
+        return match final_path.strip_prefix(&self.destination) {
+            Ok(relative_path) => self.root.join(relative_path),
+            Err(_) => self.root.clone(),
+        };
+    }
+
+    pub fn final_path(&self, staged_path: &Path) -> PathBuf {
+        return match staged_path.strip_prefix(&self.root) {
+            Ok(relative_path) => self.destination.join(relative_path),
+            Err(_) => self.destination.clone(),
+        };
+    }
+
+    /// Moves the given entries into the destination, in order, and removes the staging directory.
+    pub fn commit(&self, final_paths: &[String]) -> io::Result<()> {
+        for final_path in final_paths {
+            let final_path = Path::new(final_path);
+            let staged_path = self.staged_path(final_path);
+            let metadata = staged_path.symlink_metadata()?;
+
+            if metadata.is_dir() {
+                fs::create_dir_all(final_path)?;
+                continue;
+            }
+
+            if let Some(parent) = final_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            move_entry(&staged_path, final_path, metadata.is_symlink())?;
+        }
+
+        self.discard();
+
+        return Ok(());
+    }
+
+    /// Removes everything that was staged.
+    pub fn discard(&self) {
+        if let Err(error) = fs::remove_dir_all(&self.root) {
+            if error.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove staging directory: {}", error);
+            }
+        }
+
+        // The parents only go away if no other transfer is staged in them
+        if let Some(staging_directory) = self.root.parent() {
+            let _ = fs::remove_dir(staging_directory);
+
+            if let Some(parent) = staging_directory
+                .parent()
+                .filter(|parent| parent.file_name() == Some(JOURNAL_DIRECTORY.as_ref()))
+            {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+    }
+}
+
+/// Directory the journals and staged files of transfers into `destination` are kept in.
+/// Uses the directory set with `set_tmp_dir` if there is one, else a hidden folder in the destination.
+pub(crate) fn transfer_directory(destination: &Path) -> PathBuf {
+    return get_tmp_dir().unwrap_or_else(|| destination.join(JOURNAL_DIRECTORY));
+}
+
+/// Renames the entry, or copies it if the staging directory is on another volume.
+fn move_entry(staged_path: &Path, final_path: &Path, is_symlink: bool) -> io::Result<()> {
+    let Err(error) = fs::rename(staged_path, final_path) else {
+        return Ok(());
+    };
+
+    warn!(
+        "Failed to move {:?} into place ({}), copying it instead",
+        staged_path, error
+    );
+
+    if is_symlink {
+        #[cfg(unix)]
+        {
+            let target = fs::read_link(staged_path)?;
+            let _ = fs::remove_file(final_path);
+            std::os::unix::fs::symlink(target, final_path)?;
+        }
+    } else {
+        fs::copy(staged_path, final_path)?;
+    }
+
+    return fs::remove_file(staged_path);
+}
@@ -1,9 +1,14 @@
 use std::fmt::Debug;
 use std::io;
 use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub trait Close {
     fn close(&self);
+
+    /// Limits how long a read may block. Streams that can't time out ignore it.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) {}
 }
 
 #[uniffi::export(callback_interface)]
@@ -14,6 +19,107 @@ pub trait NativeStreamDelegate: Send + Sync + Debug {
     fn disconnect(&self);
 }
 
+/// Content provided by the host app instead of a file path, e.g. an Android content URI.
+#[uniffi::export(callback_interface)]
+pub trait NativeReadDelegate: Send + Sync + Debug {
+    /// Returns up to `buffer_length` bytes starting at `offset`.
+    /// Fewer bytes are only returned at the end of the content.
+    fn read(&self, offset: u64, buffer_length: u64) -> Vec<u8>;
+}
+
+/// Reads a `NativeReadDelegate` from the start up to the declared size.
+/// Ending early is an error, as the size was already announced in the tar header.
+pub(crate) struct NativeReader {
+    delegate: Arc<dyn NativeReadDelegate>,
+    offset: u64,
+    size: u64,
+}
+
+impl NativeReader {
+    pub fn new(delegate: Arc<dyn NativeReadDelegate>, size: u64) -> Self {
+        return Self {
+            delegate,
+            offset: 0,
+            size,
+        };
+    }
+}
+
+impl Read for NativeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.size.saturating_sub(self.offset);
+
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let length = remaining.min(buf.len() as u64);
+        let data = self.delegate.read(self.offset, length);
+
+        if data.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Native source ended before its declared size",
+            ));
+        }
+
+        let len = std::cmp::min(length as usize, data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.offset += len as u64;
+
+        Ok(len)
+    }
+}
+
+/// Destination provided by the host app instead of a directory, e.g. scoped storage on Android.
+/// Files are written one after another: `create_file`, any number of `write` calls, then `finish` or `abort`.
+#[uniffi::export(callback_interface)]
+pub trait NativeWriteDelegate: Send + Sync + Debug {
+    /// Starts a new file. `path` is relative to the share root and separated by "/".
+    /// Returns where the file ends up (e.g. a content URI), or `None` if it can't be created.
+    fn create_file(&self, path: String, size: u64) -> Option<String>;
+    /// Appends to the file created last and returns the number of bytes written.
+    fn write(&self, data: Vec<u8>) -> u64;
+    /// The file created last is complete.
+    fn finish(&self);
+    /// The file created last could not be received and should be removed.
+    fn abort(&self);
+}
+
+/// Writes to the file a `NativeWriteDelegate` created last.
+pub(crate) struct NativeWriter<'a> {
+    delegate: &'a dyn NativeWriteDelegate,
+}
+
+impl<'a> NativeWriter<'a> {
+    pub fn new(delegate: &'a dyn NativeWriteDelegate) -> Self {
+        return Self { delegate };
+    }
+}
+
+impl Write for NativeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let written = self.delegate.write(buf.to_vec()) as usize;
+
+        if written == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "Native destination did not accept any data",
+            ));
+        }
+
+        return Ok(written.min(buf.len()));
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
 impl Read for dyn NativeStreamDelegate {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let data = NativeStreamDelegate::read(self, buf.len() as u64);
@@ -1,19 +1,69 @@
+use crate::compression::{ArchiveReader, ArchiveWriter};
 use crate::encryption::EncryptedReadWrite;
+use crate::errors::{DeclaredSizeExceeded, MissingDigests, UndeclaredEntry, UnsafeArchiveError};
+use crate::integrity::{verify_files, verify_hashes, HashingReader, DIGESTS_ENTRY_TYPE};
+use crate::layout::{is_newer, portable_name, CollisionPolicy};
+use crate::manifest::ManifestSource;
 use crate::progress::{ProgressReader, ProgressWriter};
+use crate::resume::{TransferJournal, JOURNAL_DIRECTORY};
 use crate::share_store::update_progress;
+use crate::staging::Staging;
+use crate::stream::{NativeWriteDelegate, NativeWriter};
 use crate::BLE_BUFFER_SIZE;
 use crate::{SendProgressDelegate, SendProgressState};
-use log::info;
-use std::collections::HashMap;
-use std::ffi::OsString;
-use std::fs::{self, File};
-use std::io::BufWriter;
+use log::warn;
+use protocol::communication::capabilities::Compression;
+use protocol::communication::file_manifest_entry::Kind;
+use protocol::communication::FileDigests;
+use protocol::prost::Message;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
-use tar::{Archive, Builder, EntryType};
+use tar::{Archive, Builder, EntryType, Header};
+
+const DIGESTS_ENTRY_PATH: &str = ".intershare-digests";
+const MAX_DIGESTS_SIZE: u64 = 64 * 1024 * 1024;
+/// Caps for what a single archive may contain, in entries and bytes
+const MAX_ENTRIES: usize = 1 << 20;
+const MAX_PATH_LENGTH: usize = 4096;
+const MAX_NAME_LENGTH: usize = 255;
+/// Keeps the number of calls into the host app low when writing through a `NativeWriteDelegate`.
+const SINK_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Sizes announced in the request. Unpacking stops as soon as the sender exceeds them,
+/// or sends an entry that is not listed in `files`.
+pub struct DeclaredSizes {
+    /// File content still to be received, in bytes
+    pub total: u64,
+    /// Size of each selected entry, by manifest path
+    pub files: HashMap<String, u64>,
+}
+
+/// Where received entries are written to.
+pub(crate) enum ExtractTarget<'a> {
+    /// Entries are staged and only moved into the destination once all of them were received.
+    Directory {
+        staging: &'a Staging,
+        collisions: CollisionPolicy,
+    },
+    /// Only files are passed on. Directories are implied by the file paths, links are skipped.
+    Sink(&'a dyn NativeWriteDelegate),
+}
 
-fn normalize_path(path: &Path) -> String {
+#[derive(Debug)]
+pub struct UnpackedTransfer {
+    pub files: Vec<String>,
+    /// Encoded digests trailer the files were verified against
+    pub digests: Vec<u8>,
+    /// Files that did not match their digest and were deleted instead of being moved into place
+    pub failed_files: Vec<String>,
+}
+
+pub(crate) fn normalize_path(path: &Path) -> String {
     use std::path::Component;
 
     // Normal case: return the file or directory name
@@ -31,8 +81,38 @@ fn normalize_path(path: &Path) -> String {
     ".".to_string()
 }
 
-fn get_unique_path(path: &Path) -> PathBuf {
-    if !path.exists() {
+fn final_path_string(staging: &Staging, staged_path: &Path) -> String {
+    return staging
+        .final_path(staged_path)
+        .to_string_lossy()
+        .to_string();
+}
+
+/// Final path for a top-level entry. Paths claimed earlier in the same transfer are never reused,
+/// existing ones only if the collision policy allows it.
+fn claim_path(
+    candidate: PathBuf,
+    collisions: CollisionPolicy,
+    claimed_paths: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let taken = claimed_paths.contains(&candidate)
+        || (candidate.exists() && collisions == CollisionPolicy::Rename);
+
+    let path = if taken {
+        get_unique_path(&candidate, claimed_paths)
+    } else {
+        candidate
+    };
+
+    claimed_paths.insert(path.clone());
+
+    return path;
+}
+
+fn get_unique_path(path: &Path, claimed_paths: &HashSet<PathBuf>) -> PathBuf {
+    let is_free = |path: &Path| !path.exists() && !claimed_paths.contains(path);
+
+    if is_free(path) {
         return path.to_path_buf();
     }
 
@@ -52,7 +132,7 @@ fn get_unique_path(path: &Path) -> PathBuf {
 
         let new_path = path.with_file_name(new_file_name);
 
-        if !new_path.exists() {
+        if is_free(&new_path) {
             return new_path;
         }
 
@@ -60,53 +140,128 @@ fn get_unique_path(path: &Path) -> PathBuf {
     }
 }
 
-pub fn stream_tar(
+pub(crate) fn stream_tar(
     output_stream: &mut Box<dyn EncryptedReadWrite>,
-    file_paths: &Vec<String>,
+    sources: &[&ManifestSource],
+    completed_sources: &[&ManifestSource],
+    resume_offset: u64,
+    compression_level: Option<i32>,
     total_bytes: u64,
     progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
-) -> std::io::Result<()> {
-    let progress_writer = ProgressWriter::new(output_stream, |sent_bytes| {
-        if sent_bytes > 0 {
-            let mut frac = (sent_bytes as f64) / (total_bytes as f64);
-            if frac > 0.999 {
-                frac = 0.999;
-            } // avoid hitting 1.0 early
-
-            update_progress(
-                progress_delegate,
-                SendProgressState::Transferring { progress: frac },
-            )
-        }
-    });
+    cancel_flag: &AtomicBool,
+) -> std::io::Result<Vec<u8>> {
+    let archive_writer = ArchiveWriter::new(output_stream, compression_level)?;
+
+    // Progress is based on the uncompressed archive, which is what `total_bytes` refers to.
+    let progress_writer = ProgressWriter::new(
+        archive_writer,
+        |sent_bytes| {
+            if sent_bytes > 0 {
+                let mut frac = (sent_bytes as f64) / (total_bytes as f64);
+                if frac > 0.999 {
+                    frac = 0.999;
+                } // avoid hitting 1.0 early
+
+                update_progress(
+                    progress_delegate,
+                    SendProgressState::Transferring { progress: frac },
+                )
+            }
+        },
+        || cancel_flag.load(std::sync::atomic::Ordering::Relaxed),
+    );
 
     let buf_out = BufWriter::with_capacity(BLE_BUFFER_SIZE, progress_writer);
     let mut tar = Builder::new(buf_out);
+    // Nested symlinks are sent as such, just like they are announced in the manifest.
+    tar.follow_symlinks(false);
+
+    let mut digests = FileDigests::default();
+
+    for (position, source) in sources.iter().enumerate() {
+        let archive_path = &source.entry.path;
+
+        match source.entry.kind() {
+            Kind::Directory => tar.append_dir(archive_path, source_path(source)?)?,
+            Kind::Symlink => tar.append_path_with_name(source_path(source)?, archive_path)?,
+            Kind::File => {
+                let offset = if position == 0 { resume_offset } else { 0 };
+                let mut header = Header::new_gnu();
+
+                let size = match source.source.path() {
+                    Some(path) => {
+                        let metadata = fs::metadata(path)?;
+                        header.set_metadata(&metadata);
+                        metadata.len()
+                    }
+                    None => {
+                        header.set_entry_type(EntryType::Regular);
+                        header.set_mode(0o644);
+                        header.set_mtime(source.entry.modified_at.unwrap_or_default());
+                        source.entry.size
+                    }
+                };
 
-    for file_path in file_paths {
-        let path = Path::new(file_path);
-        let normalized_path = normalize_path(path);
-        info!("Normalized path: {}", normalized_path);
+                let mut reader = HashingReader::new(source.source.open(size)?.take(size));
 
-        if path.is_dir() {
-            tar.append_dir_all(&normalized_path, path)?;
-        } else {
-            let mut file = File::open(path)?;
-            tar.append_file(&normalized_path, &mut file)?;
+                // The part the receiver already has is only hashed.
+                // It appends the rest to its partial file.
+                io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+
+                header.set_size(size.saturating_sub(offset));
+                tar.append_data(&mut header, archive_path, &mut reader)?;
+
+                digests
+                    .sha256
+                    .insert(archive_path.clone(), reader.finalize().to_vec());
+            }
         }
     }
 
+    // The receiver verifies what it kept from previous attempts against the trailer as well
+    for source in completed_sources {
+        if source.entry.kind() != Kind::File {
+            continue;
+        }
+
+        let size = source.entry.size;
+        let mut reader = HashingReader::new(source.source.open(size)?.take(size));
+        io::copy(&mut reader, &mut io::sink())?;
+
+        digests
+            .sha256
+            .insert(source.entry.path.clone(), reader.finalize().to_vec());
+    }
+
+    let digests = digests.encode_to_vec();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::new(DIGESTS_ENTRY_TYPE));
+    header.set_size(digests.len() as u64);
+    header.set_mode(0o644);
+    tar.append_data(&mut header, DIGESTS_ENTRY_PATH, digests.as_slice())?;
+
     let buf_writer = tar.into_inner()?;
     let progress_writer = buf_writer.into_inner()?;
-    let stream = progress_writer.into_inner().0;
+    let stream = progress_writer.into_inner().0.finish()?;
     stream.flush()?;
+    stream.finish()?;
 
     update_progress(
         progress_delegate,
         SendProgressState::Transferring { progress: 1.0 },
     );
 
-    return Ok(());
+    return Ok(digests);
+}
+
+/// Directories and symlinks are always read from the file system.
+fn source_path(source: &ManifestSource) -> io::Result<&Path> {
+    return source.source.path().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no path to read from", source.entry.path),
+        )
+    });
 }
 
 // Keep only safe components (drop RootDir, CurDir, ParentDir, Prefix).
@@ -122,18 +277,218 @@ fn sanitize_rel_path(p: &Path) -> PathBuf {
     out
 }
 
-pub fn untar_stream<T: FnMut(f64)>(
+fn unsafe_archive(error: UnsafeArchiveError) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, error);
+}
+
+fn declared_size_exceeded(path: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, DeclaredSizeExceeded { path });
+}
+
+fn local_name(name: &OsStr) -> String {
+    let name = portable_name(&name.to_string_lossy());
+
+    if name.is_empty() {
+        return "_".to_string();
+    }
+
+    return name;
+}
+
+/// Where a path chosen by the sender is stored locally, see `portable_name`.
+/// The journal folder in the destination can't be written to by the sender.
+fn local_path(sender_path: &Path) -> io::Result<PathBuf> {
+    let mut local_path = PathBuf::new();
+
+    for component in sender_path.components() {
+        let name = local_name(component.as_os_str());
+
+        if local_path.as_os_str().is_empty() && name.eq_ignore_ascii_case(JOURNAL_DIRECTORY) {
+            return Err(unsafe_archive(UnsafeArchiveError::OutsideDestination {
+                path: sender_path.to_string_lossy().to_string(),
+            }));
+        }
+
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(unsafe_archive(UnsafeArchiveError::PathTooLong {
+                path: sender_path.to_string_lossy().to_string(),
+            }));
+        }
+
+        local_path.push(name);
+    }
+
+    return Ok(local_path);
+}
+
+/// Local target of a symlink entry, if it stays inside the folder the link was sent in.
+/// Absolute targets and links at the top level of the share are rejected.
+fn link_target(sender_path: &Path, target: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    // Number of folders the link is in, the shared folder included
+    let mut depth = sender_path.components().count().checked_sub(1)?;
+    if depth == 0 {
+        return None;
+    }
+
+    let mut local_target = PathBuf::new();
+
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => {
+                depth += 1;
+                local_target.push(local_name(name));
+            }
+            Component::ParentDir if depth > 1 => {
+                depth -= 1;
+                local_target.push("..");
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if local_target.as_os_str().is_empty() {
+        return None;
+    }
+
+    return Some(local_target);
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    if path
+        .symlink_metadata()
+        .is_ok_and(|metadata| !metadata.is_dir())
+    {
+        fs::remove_file(path)?;
+    }
+
+    return std::os::unix::fs::symlink(target, path);
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    return Err(io::Error::from(io::ErrorKind::Unsupported));
+}
+
+/// Canonicalizes the part of `path` that exists and appends the rest.
+fn resolve_existing(path: &Path) -> io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+
+    while existing.symlink_metadata().is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            break;
+        };
+
+        missing.push(name);
+        existing = parent;
+    }
+
+    let mut resolved = fs::canonicalize(existing)?;
+    resolved.extend(missing.into_iter().rev());
+
+    return Ok(resolved);
+}
+
+/// Makes sure that writing to `path` does not follow a link out of `base`,
+/// e.g. one that was received earlier in the same archive.
+fn ensure_within(base: &Path, path: &Path) -> io::Result<()> {
+    let parent = path.parent().unwrap_or(path);
+
+    if resolve_existing(parent)?.starts_with(resolve_existing(base)?) {
+        return Ok(());
+    }
+
+    return Err(unsafe_archive(UnsafeArchiveError::OutsideDestination {
+        path: path.to_string_lossy().to_string(),
+    }));
+}
+
+/// Passes a file entry on to the sink and returns its location there, along with the digest of its content.
+fn write_to_sink(
+    sink: &dyn NativeWriteDelegate,
+    path: &str,
+    entry: &mut impl Read,
+    size: u64,
+) -> io::Result<(String, [u8; 32])> {
+    let Some(location) = sink.create_file(path.to_string(), size) else {
+        return Err(io::Error::other(format!("Failed to create {}", path)));
+    };
+
+    // Reading one byte more tells whether the entry is larger than its header said
+    let mut reader = HashingReader::new(entry.take(size.saturating_add(1)));
+    let mut writer = BufWriter::with_capacity(SINK_BUFFER_SIZE, NativeWriter::new(sink));
+    let written = io::copy(&mut reader, &mut writer)
+        .and_then(|written| writer.flush().map(|_| written));
+    // Nothing may reach the sink once the file was aborted
+    drop(writer);
+
+    match written {
+        Ok(written) if written <= size => {}
+        Ok(_) => {
+            sink.abort();
+            return Err(declared_size_exceeded(path.to_string()));
+        }
+        Err(error) => {
+            sink.abort();
+            return Err(error);
+        }
+    }
+
+    sink.finish();
+
+    return Ok((location, reader.finalize()));
+}
+
+/// Unpacks a whole archive into `dest_dir`, renaming on collisions, without resuming or progress reports.
+/// Nothing is left in `dest_dir` if unpacking fails.
+#[cfg(feature = "test-utils")]
+pub fn untar_stream(
     stream: &mut Box<dyn EncryptedReadWrite>,
     dest_dir: &Path,
-    total_bytes: u64,
+    compression: Compression,
+    declared: &DeclaredSizes,
+) -> io::Result<UnpackedTransfer> {
+    let staging = Staging::open(dest_dir, None, false)?;
+    let target = ExtractTarget::Directory {
+        staging: &staging,
+        collisions: CollisionPolicy::Rename,
+    };
+
+    let unpacked = unpack_transfer(
+        stream,
+        &target,
+        compression,
+        declared,
+        |_| {},
+        &AtomicBool::new(false),
+        &mut TransferJournal::disabled(&[]),
+    );
+
+    if unpacked.is_err() {
+        staging.discard();
+    }
+
+    return unpacked;
+}
+
+pub(crate) fn unpack_transfer<T: FnMut(f64)>(
+    stream: &mut Box<dyn EncryptedReadWrite>,
+    target: &ExtractTarget,
+    compression: Compression,
+    declared: &DeclaredSizes,
     mut progress_cb: T,
     cancel_flag: &AtomicBool,
-) -> std::io::Result<Vec<String>> {
+    journal: &mut TransferJournal,
+) -> std::io::Result<UnpackedTransfer> {
     let progress_reader = ProgressReader::new(
-        stream,
+        ArchiveReader::new(stream, compression)?,
         move |bytes_read| {
-            if total_bytes > 0 {
-                let mut frac = (bytes_read as f64) / (total_bytes as f64);
+            if declared.total > 0 {
+                let mut frac = (bytes_read as f64) / (declared.total as f64);
                 if frac > 0.999 {
                     frac = 0.999;
                 }
@@ -145,79 +500,293 @@ pub fn untar_stream<T: FnMut(f64)>(
     );
 
     let mut archive = Archive::new(progress_reader);
-    let mut restored_paths = Vec::new();
-    let mut top_level_map: HashMap<OsString, PathBuf> = HashMap::new();
+    let mut restored_paths: Vec<String> = vec![];
+    let mut received_files: HashMap<String, PathBuf> = HashMap::new();
+
+    // Entries completed by previous attempts are still staged, and verified like the ones received now
+    if let ExtractTarget::Directory { staging, .. } = target {
+        for (manifest_path, staged_path) in journal.completed_targets() {
+            restored_paths.push(final_path_string(staging, &staged_path));
+
+            if staged_path
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                received_files.insert(manifest_path, staged_path);
+            }
+        }
+    }
 
-    for entry_result in archive.entries()? {
+    let mut sink_files: HashMap<String, (String, [u8; 32])> = HashMap::new();
+    let mut received_bytes: u64 = 0;
+    let mut digests = None;
+    // Targets of this transfer, so top-level entries with the same name don't end up in the same place
+    let mut claimed_paths: HashSet<PathBuf> = restored_paths.iter().map(PathBuf::from).collect();
+
+    for (position, entry_result) in archive.entries()?.enumerate() {
         if cancel_flag.load(std::sync::atomic::Ordering::Relaxed) {
             break;
         }
 
+        if position >= MAX_ENTRIES {
+            return Err(unsafe_archive(UnsafeArchiveError::TooManyEntries {
+                limit: MAX_ENTRIES,
+            }));
+        }
+
         let mut entry = entry_result?;
+
+        if entry.header().entry_type() == EntryType::new(DIGESTS_ENTRY_TYPE) {
+            let mut trailer = Vec::new();
+            (&mut entry)
+                .take(MAX_DIGESTS_SIZE)
+                .read_to_end(&mut trailer)?;
+            digests = Some(trailer);
+            continue;
+        }
+
+        if entry.path_bytes().len() > MAX_PATH_LENGTH {
+            return Err(unsafe_archive(UnsafeArchiveError::PathTooLong {
+                path: String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+            }));
+        }
+
         let raw_rel_path = entry.path().map(|p| p.into_owned()).unwrap_or_default();
         let clean_rel_path = sanitize_rel_path(&raw_rel_path);
         if clean_rel_path.as_os_str().is_empty() {
             continue;
         }
 
-        let mut components = clean_rel_path.components();
+        // Paths as sent, used to look up the manifest, journal and digests
+        let manifest_path: Vec<String> = clean_rel_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let manifest_path = manifest_path.join("/");
+
+        let Some(declared_size) = declared.files.get(&manifest_path).copied() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                UndeclaredEntry {
+                    path: manifest_path,
+                },
+            ));
+        };
+
+        let entry_type = entry.header().entry_type();
+        let is_file = matches!(
+            entry_type,
+            EntryType::Regular | EntryType::GNUSparse | EntryType::Continuous
+        );
+        let is_symlink = entry_type == EntryType::Symlink;
+
+        if !is_file && !is_symlink && entry_type != EntryType::Directory {
+            // Hard links, devices and the like
+            warn!(
+                "Skipping {} with unsupported entry type {:?}",
+                manifest_path, entry_type
+            );
+            continue;
+        }
+
+        let link_target = if is_symlink {
+            let target = entry
+                .link_name()?
+                .and_then(|target| link_target(&clean_rel_path, &target))
+                .filter(|_| cfg!(unix));
+
+            let Some(target) = target else {
+                warn!(
+                    "Skipping link {}, its target is not supported",
+                    manifest_path
+                );
+                continue;
+            };
+
+            Some(target)
+        } else {
+            None
+        };
+
+        let local_rel_path = local_path(&clean_rel_path)?;
+        let mut components = local_rel_path.components();
         let root_component = match components.next() {
             Some(std::path::Component::Normal(seg)) => OsString::from(seg),
             _ => continue,
         };
         let sub_path: PathBuf = components.as_path().to_path_buf();
-        let entry_type = entry.header().entry_type();
 
-        let target_path = if sub_path.as_os_str().is_empty()
-            && matches!(
-                entry_type,
-                EntryType::Regular | EntryType::GNUSparse | EntryType::Continuous
-            ) {
-            let mut file_path = dest_dir.join(&root_component);
-            if file_path.exists() {
-                file_path = get_unique_path(&file_path);
+        let entry_index = journal.entry_index(&manifest_path);
+        let resumed_target = entry_index.and_then(|index| journal.resumed_target(index).cloned());
+
+        let size = entry.header().size()?;
+
+        if is_file {
+            received_bytes = received_bytes.saturating_add(size);
+
+            if size > declared_size || received_bytes > declared.total {
+                return Err(declared_size_exceeded(manifest_path));
             }
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
+        }
+
+        let (staging, collisions) = match target {
+            ExtractTarget::Directory {
+                staging,
+                collisions,
+            } => (*staging, *collisions),
+            ExtractTarget::Sink(sink) => {
+                if is_file {
+                    let local_path: Vec<String> = local_rel_path
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                        .collect();
+                    let (location, digest) =
+                        write_to_sink(*sink, &local_path.join("/"), &mut entry, size)?;
+
+                    restored_paths.push(location.clone());
+                    sink_files.insert(manifest_path, (location, digest));
+                }
+
+                continue;
             }
+        };
+
+        let dest_dir = staging.destination();
+
+        let target_path = if let Some(resumed_target) = &resumed_target {
+            staging.final_path(resumed_target)
+        } else if sub_path.as_os_str().is_empty() && is_file {
+            let file_path = claim_path(
+                dest_dir.join(&root_component),
+                collisions,
+                &mut claimed_paths,
+            );
+            ensure_within(dest_dir, &file_path)?;
             file_path
         } else {
-            let root_target = top_level_map
-                .entry(root_component.clone())
-                .or_insert_with(|| {
-                    let candidate = dest_dir.join(&root_component);
-                    if candidate.exists() {
-                        get_unique_path(&candidate)
-                    } else {
-                        candidate
-                    }
-                });
+            let root_target = match journal.root_target(&root_component) {
+                Some(root_target) => root_target.clone(),
+                None => {
+                    // Other policies merge into the existing folder and apply to each file inside
+                    let root_target = claim_path(
+                        dest_dir.join(&root_component),
+                        collisions,
+                        &mut claimed_paths,
+                    );
+
+                    journal.record_root(&root_component, &root_target);
+                    root_target
+                }
+            };
 
-            let full_path = if sub_path.as_os_str().is_empty() {
+            if sub_path.as_os_str().is_empty() {
+                ensure_within(dest_dir, &root_target)?;
                 root_target.clone()
             } else {
-                root_target.join(&sub_path)
+                let full_path = root_target.join(&sub_path);
+
+                // Checked in the destination for existing links, and staged for links of this transfer
+                ensure_within(&root_target, &full_path)?;
+                ensure_within(
+                    &staging.staged_path(&root_target),
+                    &staging.staged_path(&full_path),
+                )?;
+                full_path
+            }
+        };
+
+        let staged_path = staging.staged_path(&target_path);
+
+        let exists = target_path.symlink_metadata().is_ok();
+
+        if (is_file || is_symlink) && resumed_target.is_none() && exists {
+            let keep_existing = match collisions {
+                CollisionPolicy::Rename | CollisionPolicy::Overwrite => false,
+                CollisionPolicy::Skip => true,
+                CollisionPolicy::KeepNewest => !is_newer(&target_path, entry.header().mtime()?),
             };
 
-            if let Some(parent) = full_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
+            if keep_existing {
+                if let Some(index) = entry_index {
+                    journal.record_completed(index);
+                }
 
-            full_path
-        };
+                continue;
+            }
+        }
 
         restored_paths.push(target_path.to_string_lossy().to_string());
 
+        if let (Some(index), None) = (entry_index, &resumed_target) {
+            journal.record_started(index, &staged_path);
+        }
+
+        if let Some(parent) = staged_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
         match entry_type {
             EntryType::Directory => {
-                fs::create_dir_all(&target_path)?;
+                fs::create_dir_all(&staged_path)?;
             }
             EntryType::Regular | EntryType::GNUSparse | EntryType::Continuous => {
-                entry.unpack(&target_path)?;
+                if resumed_target.is_some() {
+                    // The sender only sends the part of the file that is still missing.
+                    let mut file = OpenOptions::new().append(true).open(&staged_path)?;
+                    io::copy(&mut entry, &mut file)?;
+                } else {
+                    entry.unpack(&staged_path)?;
+                }
+
+                // The header size is only what was stored, e.g. sparse entries unpack to more
+                if fs::metadata(&staged_path)?.len() > declared_size {
+                    return Err(declared_size_exceeded(manifest_path));
+                }
+
+                received_files.insert(manifest_path, staged_path);
+            }
+            EntryType::Symlink => {
+                if let Some(link_target) = &link_target {
+                    create_symlink(link_target, &staged_path)?;
+                }
             }
             _ => {}
         }
+
+        if let Some(index) = entry_index {
+            journal.record_completed(index);
+        }
+    }
+
+    // Consume the rest of the archive up to the final record, so a truncated stream is detected.
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+
+    // Without the trailer, nothing received can be trusted
+    let Some(digests) = digests else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, MissingDigests));
+    };
+
+    let file_digests = FileDigests::decode(digests.as_slice())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let mut failed_files = verify_files(&file_digests.sha256, &received_files);
+    failed_files.extend(verify_hashes(&file_digests.sha256, &sink_files));
+
+    if let ExtractTarget::Directory { staging, .. } = target {
+        for failed_file in &mut failed_files {
+            *failed_file = final_path_string(staging, Path::new(failed_file));
+        }
+    }
+
+    restored_paths.retain(|path| !failed_files.contains(path));
+
+    if let ExtractTarget::Directory { staging, .. } = target {
+        staging.commit(&restored_paths)?;
     }
 
-    Ok(restored_paths)
+    Ok(UnpackedTransfer {
+        files: restored_paths,
+        digests,
+        failed_files,
+    })
 }
@@ -0,0 +1,425 @@
+use crate::convert_os_str;
+use crate::manifest::scan;
+use crate::nearby_server::{InternalNearbyServer, ShareStores};
+use crate::share_store::ShareStore;
+use log::{info, warn};
+use protocol::communication::convenience_download_response::Status;
+use protocol::communication::file_manifest_entry::Kind;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tokio::task::JoinHandle;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const MAX_REQUEST_HEADER_SIZE: usize = 8 * 1024;
+const ARCHIVE_NAME: &str = "InterShare.zip";
+
+/// Minimal HTTP/1.1 server, so convenience shares can be downloaded with any browser on the LAN.
+pub struct HttpServer {
+    pub port: u16,
+    running: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl HttpServer {
+    pub fn stop(self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.task.abort();
+    }
+}
+
+enum Route {
+    Page,
+    File(usize),
+    Archive,
+}
+
+impl InternalNearbyServer {
+    pub(crate) fn new_http_server(&self) -> Result<HttpServer, io::Error> {
+        let addresses = [
+            SocketAddr::from(([0, 0, 0, 0], 4252)),
+            SocketAddr::from(([0, 0, 0, 0], 0)),
+        ];
+
+        let listener = TcpListener::bind(&addresses[..])?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        info!("Started http listener on port {}", port);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let share_stores = self.share_stores.clone();
+
+        let task = tokio::spawn({
+            let running = running.clone();
+
+            async move {
+                let listener = tokio::net::TcpListener::from_std(listener)
+                    .expect("Failed to register listener");
+
+                while running.load(Ordering::SeqCst) {
+                    let Ok((stream, _socket_address)) = listener.accept().await else {
+                        continue;
+                    };
+
+                    let Ok(stream) = stream.into_std() else {
+                        continue;
+                    };
+
+                    let share_stores = share_stores.clone();
+
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(error) = handle_connection(stream, share_stores) {
+                            warn!("Failed to answer http request: {}", error);
+                        }
+                    });
+                }
+            }
+        });
+
+        return Ok(HttpServer {
+            port,
+            running,
+            task,
+        });
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, share_stores: ShareStores) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+
+    let Some(request_line) = read_request_line(&mut stream)? else {
+        return write_error(&mut stream, "400 Bad Request", "Bad request");
+    };
+
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return write_error(&mut stream, "400 Bad Request", "Bad request");
+    };
+
+    if method != "GET" {
+        return write_error(&mut stream, "405 Method Not Allowed", "Method not allowed");
+    }
+
+    let Some((request_id, route, session)) = parse_route(target) else {
+        return write_error(&mut stream, "404 Not Found", "This share does not exist.");
+    };
+
+    let share_store = share_stores.blocking_write().lookup(&request_id);
+
+    let share_store = match share_store {
+        Ok(share_store) => share_store,
+        Err(status) => return write_status_error(&mut stream, status),
+    };
+
+    if let Err(status) = share_store.check_http_access() {
+        return write_status_error(&mut stream, status);
+    }
+
+    // Opening the page counts as one download. The files and the archive it links to are
+    // not counted again, unless they are requested without the page's session.
+    if !matches!(route, Route::Page) && !share_store.reserve_http_download(session.as_deref()) {
+        return write_status_error(&mut stream, Status::Exhausted);
+    }
+
+    return match route {
+        Route::Page => {
+            let Some(session) = share_store.start_http_session() else {
+                return write_status_error(&mut stream, Status::Exhausted);
+            };
+
+            write_page(&mut stream, &share_store, &session)
+        }
+        Route::File(index) => write_file(&mut stream, &share_store, index),
+        Route::Archive => {
+            let file_paths = share_store.file_paths.clone().unwrap_or_default();
+            let archive = share_store.http_archive(None, || build_archive(&file_paths))?;
+            write_download(&mut stream, archive, ARCHIVE_NAME, "application/zip")
+        }
+    };
+}
+
+fn read_request_line(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut header = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !header.windows(4).any(|window| window == b"\r\n\r\n") {
+        if header.len() > MAX_REQUEST_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let read = stream.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        header.extend_from_slice(&buffer[..read]);
+    }
+
+    let header = String::from_utf8_lossy(&header);
+    return Ok(header.lines().next().map(|line| line.to_string()));
+}
+
+/// `/<request_id>`, `/<request_id>/files/<index>` or `/<request_id>/archive.zip`,
+/// optionally followed by `?session=<token>`.
+fn parse_route(target: &str) -> Option<(String, Route, Option<String>)> {
+    let target = target.split('#').next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let session = query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("session="))
+        .map(|session| session.to_string());
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let route = match segments.as_slice() {
+        [_] => Route::Page,
+        [_, "files", index] => Route::File(index.parse().ok()?),
+        [_, "archive.zip"] => Route::Archive,
+        _ => return None,
+    };
+
+    let request_id = segments.first()?.to_string();
+
+    if request_id.is_empty() {
+        return None;
+    }
+
+    return Some((request_id, route, session));
+}
+
+fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    content_length: u64,
+    content_disposition: Option<String>,
+) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\nCache-Control: no-store\r\nReferrer-Policy: no-referrer\r\nX-Content-Type-Options: nosniff\r\n",
+        status, content_type, content_length
+    );
+
+    if let Some(content_disposition) = content_disposition {
+        head.push_str(&format!("Content-Disposition: {}\r\n", content_disposition));
+    }
+
+    head.push_str("\r\n");
+
+    return stream.write_all(head.as_bytes());
+}
+
+fn write_html(stream: &mut TcpStream, status: &str, title: &str, body: &str) -> io::Result<()> {
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{0}</title><style>body{{font-family:-apple-system,system-ui,sans-serif;max-width:40rem;margin:2rem auto;padding:0 1rem;line-height:1.5}}pre{{white-space:pre-wrap;word-break:break-word;background:#f2f2f2;padding:1rem;border-radius:.5rem}}li{{margin:.25rem 0}}</style></head><body><h1>{0}</h1>{1}</body></html>",
+        escape_html(title),
+        body
+    );
+
+    write_head(
+        stream,
+        status,
+        "text/html; charset=utf-8",
+        html.len() as u64,
+        None,
+    )?;
+    return stream.write_all(html.as_bytes());
+}
+
+fn write_error(stream: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+    return write_html(
+        stream,
+        status,
+        "InterShare",
+        &format!("<p>{}</p>", escape_html(message)),
+    );
+}
+
+fn write_status_error(stream: &mut TcpStream, status: Status) -> io::Result<()> {
+    return match status {
+        Status::Expired => write_error(stream, "410 Gone", "This share has expired."),
+        Status::Exhausted => write_error(
+            stream,
+            "410 Gone",
+            "This share reached its maximum number of downloads.",
+        ),
+        Status::Revoked => write_error(stream, "410 Gone", "This share was revoked."),
+        Status::AccessCodeRequired
+        | Status::InvalidAccessCode
+        | Status::DeviceNotAllowed
+        | Status::Locked => write_error(
+            stream,
+            "403 Forbidden",
+            "This share can only be downloaded with the InterShare app.",
+        ),
+        Status::Accepted | Status::NotFound => {
+            write_error(stream, "404 Not Found", "This share does not exist.")
+        }
+    };
+}
+
+fn write_page(stream: &mut TcpStream, share_store: &ShareStore, session: &str) -> io::Result<()> {
+    let title = match share_store.get_sender_name() {
+        Some(name) => format!("{} shared with you", name),
+        None => "Shared with you".to_string(),
+    };
+
+    let Some(file_paths) = &share_store.file_paths else {
+        let text = share_store.clipboard.clone().unwrap_or_default();
+        let body = format!("<pre>{}</pre>", escape_html(&text));
+        return write_html(stream, "200 OK", &title, &body);
+    };
+
+    let mut body = String::from("<ul>");
+
+    for (index, file_path) in file_paths.iter().enumerate() {
+        body.push_str(&format!(
+            "<li><a href=\"/{}/files/{}?session={}\">{}</a></li>",
+            share_store.request_id,
+            index,
+            session,
+            escape_html(&display_name(Path::new(file_path)))
+        ));
+    }
+
+    body.push_str("</ul>");
+
+    if file_paths.len() > 1 {
+        body.push_str(&format!(
+            "<p><a href=\"/{}/archive.zip?session={}\">Download all as zip</a></p>",
+            share_store.request_id, session
+        ));
+    }
+
+    return write_html(stream, "200 OK", &title, &body);
+}
+
+fn write_file(stream: &mut TcpStream, share_store: &ShareStore, index: usize) -> io::Result<()> {
+    let Some(file_path) = share_store
+        .file_paths
+        .as_ref()
+        .and_then(|file_paths| file_paths.get(index))
+    else {
+        return write_error(stream, "404 Not Found", "This file does not exist.");
+    };
+
+    let path = Path::new(file_path);
+    let name = display_name(path);
+
+    if path.is_dir() {
+        let archive = share_store.http_archive(Some(index), || {
+            build_archive(std::slice::from_ref(file_path))
+        })?;
+        return write_download(stream, archive, &format!("{}.zip", name), "application/zip");
+    }
+
+    let file = File::open(path)?;
+    return write_download(stream, file, &name, "application/octet-stream");
+}
+
+fn write_download(
+    stream: &mut TcpStream,
+    mut file: File,
+    file_name: &str,
+    content_type: &str,
+) -> io::Result<()> {
+    let length = file.metadata()?.len();
+
+    write_head(
+        stream,
+        "200 OK",
+        content_type,
+        length,
+        Some(content_disposition(file_name)),
+    )?;
+
+    io::copy(&mut file, stream)?;
+    return stream.flush();
+}
+
+/// Zips the shared paths into a temporary file, so the response can announce its length.
+/// Uses the same paths as a transfer to the app, see `scan`.
+fn build_archive(file_paths: &[String]) -> io::Result<NamedTempFile> {
+    let mut zip = ZipWriter::new(NamedTempFile::new()?);
+    let options = SimpleFileOptions::default().large_file(true);
+
+    for source in scan(file_paths)? {
+        match (source.entry.kind(), source.source.path()) {
+            (Kind::Directory, _) => {
+                zip.add_directory(source.entry.path, options)
+                    .map_err(io::Error::other)?;
+            }
+            (Kind::File, Some(path)) => {
+                zip.start_file(source.entry.path, options)
+                    .map_err(io::Error::other)?;
+                io::copy(&mut File::open(path)?, &mut zip)?;
+            }
+            // Symlinks below the shared paths are not followed
+            _ => {}
+        }
+    }
+
+    return zip.finish().map_err(io::Error::other);
+}
+
+fn display_name(path: &Path) -> String {
+    return path
+        .file_name()
+        .map(convert_os_str)
+        .unwrap_or_else(|| "file".to_string());
+}
+
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|character| {
+            if character.is_ascii_graphic() && character != '"' && character != '\\' {
+                character
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let encoded: String = file_name
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect();
+
+    return format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    );
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+
+    return escaped;
+}
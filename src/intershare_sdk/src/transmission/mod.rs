@@ -1,9 +1,13 @@
 use thiserror::Error;
 
+pub mod http;
 pub mod tcp;
 
 #[derive(Error, Debug, uniffi::Error)]
 pub enum TransmissionSetupError {
     #[error("Unable to start TCP server: {error}")]
     UnableToStartTcpServer { error: String },
+
+    #[error("Unable to start HTTP server: {error}")]
+    UnableToStartHttpServer { error: String },
 }
@@ -1,8 +1,10 @@
 use crate::communication::initiate_receiver_communication;
 use crate::connection_request::ConnectionRequest;
-use crate::nearby_server::{InternalNearbyServer, NearbyConnectionDelegate};
+use crate::identity::DeviceIdentity;
+use crate::nearby_server::{InternalNearbyServer, NearbyConnectionDelegate, ShareStores};
+use crate::share_store::ConnectionMedium;
 use crate::stream::Close;
-use log::info;
+use log::{error, info, warn};
 use prost_stream::Stream;
 use protocol::communication::request::RequestTypes;
 use protocol::communication::Request;
@@ -15,10 +17,15 @@ use std::time::Duration;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 
+/// How long a connecting peer may take for the handshake and its request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct TcpServer {
     pub port: u16,
     listener: Option<TcpListener>,
     delegate: Arc<RwLock<Box<dyn NearbyConnectionDelegate>>>,
+    identity: Arc<DeviceIdentity>,
+    share_stores: ShareStores,
     file_storage: String,
     running: Arc<AtomicBool>,
     tcp_server_task: RwLock<Option<JoinHandle<()>>>,
@@ -49,6 +56,8 @@ impl InternalNearbyServer {
             port,
             listener: Some(listener),
             delegate,
+            identity: self.identity.clone(),
+            share_stores: self.share_stores.clone(),
             file_storage,
             running: Arc::new(AtomicBool::new(true)),
             tcp_server_task: RwLock::new(None),
@@ -76,13 +85,22 @@ impl InternalNearbyServer {
             .set_nonblocking(true)
             .expect("Failed to set non blocking");
         let delegate = tcp_server.delegate.clone();
+        let identity = tcp_server.identity.clone();
+        let share_stores = tcp_server.share_stores.clone();
         let file_storage = tcp_server.file_storage.clone();
         let running = tcp_server.running.clone();
 
         let handle = tokio::spawn(async move {
+            let listener =
+                tokio::net::TcpListener::from_std(listener).expect("Failed to register listener");
+
             info!("Started loop");
             while running.load(Ordering::SeqCst) {
-                let Ok((tcp_stream, _socket_address)) = listener.accept() else {
+                let Ok((tcp_stream, _socket_address)) = listener.accept().await else {
+                    continue;
+                };
+
+                let Ok(tcp_stream) = tcp_stream.into_std() else {
                     continue;
                 };
 
@@ -90,37 +108,15 @@ impl InternalNearbyServer {
                     .set_nonblocking(false)
                     .expect("Failed to set non blocking");
 
-                let mut encrypted_stream = match initiate_receiver_communication(tcp_stream) {
-                    Ok(request) => request,
-                    Err(error) => {
-                        println!("Encryption error {:}", error);
-                        continue;
-                    }
-                };
+                // The handshake blocks, so a silent peer must not hold up the accept loop
+                let delegate = delegate.clone();
+                let identity = identity.clone();
+                let share_stores = share_stores.clone();
+                let file_storage = file_storage.clone();
 
-                let mut prost_stream = Stream::new(&mut encrypted_stream);
-                let transfer_request = match prost_stream.recv::<Request>() {
-                    Ok(message) => message,
-                    Err(error) => {
-                        println!("Error {:}", error);
-                        continue;
-                    }
-                };
-
-                if transfer_request.r#type == RequestTypes::ShareRequest as i32 {
-                    let connection_request = ConnectionRequest::new(
-                        transfer_request,
-                        Box::new(encrypted_stream),
-                        file_storage.clone(),
-                    );
-
-                    delegate
-                        .read()
-                        .await
-                        .received_connection_request(Arc::new(connection_request));
-                } else {
-                    // NearbyServer::received_convenience_download_request(transfer_request, current_share_store.clone()).await;
-                }
+                tokio::task::spawn_blocking(move || {
+                    handle_connection(tcp_stream, delegate, identity, share_stores, file_storage);
+                });
             }
 
             info!("Stopped loop");
@@ -154,6 +150,62 @@ impl InternalNearbyServer {
     }
 }
 
+/// Runs the handshake and reads the request of an accepted connection, on a blocking thread.
+fn handle_connection(
+    tcp_stream: TcpStream,
+    delegate: Arc<RwLock<Box<dyn NearbyConnectionDelegate>>>,
+    identity: Arc<DeviceIdentity>,
+    share_stores: ShareStores,
+    file_storage: String,
+) {
+    if let Err(error) = tcp_stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)) {
+        warn!("Failed to set read timeout: {}", error);
+    }
+
+    let (mut encrypted_stream, session) =
+        match initiate_receiver_communication(tcp_stream, &identity) {
+            Ok(result) => result,
+            Err(error) => {
+                error!("Encryption error {:}", error);
+                return;
+            }
+        };
+
+    let mut prost_stream = Stream::new(&mut encrypted_stream);
+    let transfer_request = match prost_stream.recv::<Request>() {
+        Ok(message) => message,
+        Err(error) => {
+            error!("Error {:}", error);
+            return;
+        }
+    };
+
+    if transfer_request.r#type == RequestTypes::ShareRequest as i32 {
+        // Accepting a request may take the user a while
+        encrypted_stream.set_read_timeout(None);
+
+        let connection_request = ConnectionRequest::new(
+            transfer_request,
+            Box::new(encrypted_stream),
+            session,
+            identity,
+            file_storage,
+        );
+
+        delegate
+            .blocking_read()
+            .received_connection_request(Arc::new(connection_request));
+    } else {
+        InternalNearbyServer::received_convenience_download_request(
+            transfer_request,
+            Box::new(encrypted_stream),
+            session,
+            ConnectionMedium::WiFi,
+            share_stores,
+        );
+    }
+}
+
 pub struct TcpClient {}
 
 impl TcpClient {
@@ -171,4 +223,10 @@ impl Close for TcpStream {
     fn close(&self) {
         // Do nothing. TCPStream closes automatically.
     }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) {
+        if let Err(error) = TcpStream::set_read_timeout(self, timeout) {
+            warn!("Failed to set read timeout: {}", error);
+        }
+    }
 }
@@ -0,0 +1,120 @@
+use crate::get_config_dir;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use log::{error, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+const TRUST_STORE_FILE_NAME: &str = "trusted_devices";
+
+static TRUST_STORE: OnceLock<RwLock<TrustStore>> = OnceLock::new();
+
+#[derive(uniffi::Enum, Clone, Copy, PartialEq, Debug)]
+pub enum TrustLevel {
+    /// The device presented the identity key pinned for its id.
+    Verified,
+    /// No identity key was pinned for this device id yet.
+    New,
+    /// The device presented a different identity key than the one pinned for its id.
+    KeyChanged,
+}
+
+/// Trust-on-first-use store, pinning one identity key per device id.
+pub struct TrustStore {
+    path: Option<PathBuf>,
+    pinned_keys: HashMap<String, [u8; 32]>,
+}
+
+pub fn trust_store() -> &'static RwLock<TrustStore> {
+    TRUST_STORE.get_or_init(|| {
+        RwLock::new(TrustStore::load(
+            get_config_dir().map(|config_dir| config_dir.join(TRUST_STORE_FILE_NAME)),
+        ))
+    })
+}
+
+impl TrustStore {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut pinned_keys = HashMap::new();
+
+        if path.is_none() {
+            warn!("No config directory available. Pinned device keys will not be persisted.");
+        }
+
+        if let Some(content) = path.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+            for line in content.lines() {
+                let Some((device_id, encoded_key)) = line.rsplit_once(' ') else {
+                    continue;
+                };
+
+                let Ok(key) = STANDARD.decode(encoded_key) else {
+                    continue;
+                };
+
+                if let Ok(key) = <[u8; 32]>::try_from(key.as_slice()) {
+                    pinned_keys.insert(device_id.to_string(), key);
+                }
+            }
+        }
+
+        return Self { path, pinned_keys };
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let content: String = self
+            .pinned_keys
+            .iter()
+            .map(|(device_id, key)| format!("{} {}\n", device_id, STANDARD.encode(key)))
+            .collect();
+
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        if let Err(error) = fs::write(path, content) {
+            error!("Failed to store trusted devices: {}", error);
+        }
+    }
+
+    /// Compares `key` against the pinned key without modifying the store.
+    pub fn check(&self, device_id: &str, key: &[u8; 32]) -> TrustLevel {
+        return match self.pinned_keys.get(device_id) {
+            None => TrustLevel::New,
+            Some(pinned_key) if pinned_key == key => TrustLevel::Verified,
+            Some(_) => TrustLevel::KeyChanged,
+        };
+    }
+
+    /// Like `check`, but pins the key if the device was not known yet.
+    /// A changed key is never replaced implicitly, see `pin`.
+    pub fn check_and_pin(&mut self, device_id: &str, key: &[u8; 32]) -> TrustLevel {
+        let trust_level = self.check(device_id, key);
+
+        if trust_level == TrustLevel::New {
+            self.pin(device_id, key);
+        }
+
+        return trust_level;
+    }
+
+    pub fn pin(&mut self, device_id: &str, key: &[u8; 32]) {
+        self.pinned_keys.insert(device_id.to_string(), *key);
+        self.save();
+    }
+
+    pub fn forget(&mut self, device_id: &str) {
+        if self.pinned_keys.remove(device_id).is_some() {
+            self.save();
+        }
+    }
+}
+
+#[uniffi::export]
+pub fn forget_trusted_device(device_id: String) {
+    trust_store().write().unwrap().forget(&device_id);
+}
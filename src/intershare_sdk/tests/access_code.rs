@@ -0,0 +1,124 @@
+use intershare_sdk::access_code::{
+    confirmation_matches, AccessCodeAttempts, AccessCodeExchange, ACCESS_CODE_LOCKOUT,
+    MAX_ACCESS_CODE_ATTEMPTS,
+};
+use intershare_sdk::capabilities::{local_capabilities, negotiate};
+use intershare_sdk::communication::Session;
+use rand_core::{OsRng, RngCore};
+use std::time::{Duration, Instant};
+
+fn session() -> Session {
+    let mut transcript_hash = [0u8; 32];
+    OsRng.fill_bytes(&mut transcript_hash);
+
+    return Session {
+        transcript_hash,
+        peer_identity_key: [0u8; 32],
+        capabilities: negotiate(&local_capabilities(), &local_capabilities()).unwrap(),
+    };
+}
+
+#[test]
+pub fn matching_access_codes_confirm() {
+    let session = session();
+
+    let (sharer, sharer_message) = AccessCodeExchange::start("correct horse", &session);
+    let (downloader, downloader_message) = AccessCodeExchange::start("correct horse", &session);
+
+    let sharer = sharer.finish(&downloader_message).unwrap();
+    let downloader = downloader.finish(&sharer_message).unwrap();
+
+    assert!(confirmation_matches(&sharer.sharer, &downloader.sharer));
+    assert!(confirmation_matches(
+        &downloader.downloader,
+        &sharer.downloader
+    ));
+    assert!(!confirmation_matches(&sharer.sharer, &sharer.downloader));
+}
+
+#[test]
+pub fn wrong_access_code_is_rejected() {
+    let session = session();
+
+    let (sharer, sharer_message) = AccessCodeExchange::start("123456", &session);
+    let (downloader, downloader_message) = AccessCodeExchange::start("123457", &session);
+
+    let sharer = sharer.finish(&downloader_message).unwrap();
+    let downloader = downloader.finish(&sharer_message).unwrap();
+
+    assert!(!confirmation_matches(&sharer.sharer, &downloader.sharer));
+    assert!(!confirmation_matches(
+        &sharer.downloader,
+        &downloader.downloader
+    ));
+}
+
+#[test]
+pub fn access_code_is_bound_to_session() {
+    let (sharer, sharer_message) = AccessCodeExchange::start("123456", &session());
+    let (downloader, downloader_message) = AccessCodeExchange::start("123456", &session());
+
+    let sharer = sharer.finish(&downloader_message).unwrap();
+    let downloader = downloader.finish(&sharer_message).unwrap();
+
+    assert!(!confirmation_matches(&sharer.sharer, &downloader.sharer));
+}
+
+#[test]
+pub fn correct_code_works_again_after_lockout() {
+    let mut attempts = AccessCodeAttempts::default();
+    let now = Instant::now();
+
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS {
+        assert!(attempts.reserve(now));
+        attempts.failed(now);
+    }
+
+    assert!(!attempts.reserve(now));
+    assert!(!attempts.reserve(now + ACCESS_CODE_LOCKOUT - Duration::from_secs(1)));
+
+    let unlocked = now + ACCESS_CODE_LOCKOUT;
+    assert!(attempts.reserve(unlocked));
+    attempts.succeeded();
+
+    assert!(attempts.reserve(unlocked));
+}
+
+#[test]
+pub fn lockout_grows_with_every_lock() {
+    let mut attempts = AccessCodeAttempts::default();
+    let mut now = Instant::now();
+
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS {
+        assert!(attempts.reserve(now));
+        attempts.failed(now);
+    }
+
+    now += ACCESS_CODE_LOCKOUT;
+
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS {
+        assert!(attempts.reserve(now));
+        attempts.failed(now);
+    }
+
+    assert!(!attempts.reserve(now + ACCESS_CODE_LOCKOUT));
+    assert!(attempts.reserve(now + ACCESS_CODE_LOCKOUT * 2));
+}
+
+#[test]
+pub fn abandoned_attempts_are_not_counted() {
+    let mut attempts = AccessCodeAttempts::default();
+    let now = Instant::now();
+
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS * 2 {
+        assert!(attempts.reserve(now));
+        attempts.abandoned();
+    }
+
+    // Attempts still in progress count towards the limit
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS {
+        assert!(attempts.reserve(now));
+    }
+
+    assert!(!attempts.reserve(now));
+}
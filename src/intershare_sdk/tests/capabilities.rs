@@ -0,0 +1,58 @@
+use intershare_sdk::capabilities::{
+    decode_capabilities, encode_capabilities, local_capabilities, negotiate,
+};
+use intershare_sdk::errors::IncomingErrors;
+use intershare_sdk::{is_compatible, VersionCompatibility};
+use protocol::communication::capabilities::{Compression, IntentType};
+use protocol::discovery::Device;
+
+#[test]
+fn negotiates_common_capabilities() {
+    let mut receiver = local_capabilities();
+    receiver.max_protocol_version += 1;
+    receiver.intents = vec![IntentType::Clipboard as i32];
+    receiver.compression = vec![Compression::None as i32];
+
+    let encoded = encode_capabilities(&receiver);
+    let negotiated = negotiate(
+        &local_capabilities(),
+        &decode_capabilities(&encoded).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        negotiated.protocol_version,
+        local_capabilities().max_protocol_version
+    );
+    assert_eq!(negotiated.compression, Compression::None);
+    assert!(negotiated.supports_intent(IntentType::Clipboard));
+    assert!(!negotiated.supports_intent(IntentType::FileTransfer));
+}
+
+#[test]
+fn rejects_incompatible_protocol_versions() {
+    let mut receiver = local_capabilities();
+    receiver.min_protocol_version = local_capabilities().max_protocol_version + 1;
+    receiver.max_protocol_version = receiver.min_protocol_version;
+
+    assert!(matches!(
+        negotiate(&local_capabilities(), &receiver),
+        Err(IncomingErrors::IncompatibleCapabilities)
+    ));
+}
+
+#[test]
+fn reports_legacy_devices_as_unsupported() {
+    let device = Device {
+        id: "legacy".to_string(),
+        name: "Legacy".to_string(),
+        device_type: 0,
+        protocol_version: Some(0),
+        min_protocol_version: None,
+    };
+
+    assert_eq!(
+        is_compatible(device),
+        VersionCompatibility::UnsupportedLegacyVersion
+    );
+}
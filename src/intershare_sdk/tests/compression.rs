@@ -0,0 +1,30 @@
+use intershare_sdk::compression::{is_compressible, should_compress};
+use intershare_sdk::FileManifestEntry;
+
+fn entry(size: u64, mime_type: &str) -> FileManifestEntry {
+    return FileManifestEntry {
+        size,
+        mime_type: Some(mime_type.to_string()),
+        ..Default::default()
+    };
+}
+
+#[test]
+fn skips_compression_for_compressed_formats() {
+    assert!(is_compressible(Some("text/plain")));
+    assert!(is_compressible(Some("image/svg+xml")));
+    assert!(is_compressible(None));
+    assert!(!is_compressible(Some("image/jpeg")));
+    assert!(!is_compressible(Some("video/mp4")));
+    assert!(!is_compressible(Some("application/zip")));
+
+    assert!(should_compress(&[
+        entry(600, "text/plain"),
+        entry(400, "image/jpeg")
+    ]));
+    assert!(!should_compress(&[
+        entry(100, "text/plain"),
+        entry(4000, "video/mp4")
+    ]));
+    assert!(!should_compress(&[]));
+}
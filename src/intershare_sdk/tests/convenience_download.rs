@@ -0,0 +1,570 @@
+use crate::helper::{device, isolate_config_dir, start_downloader, start_sharer};
+use intershare_sdk::access_code::MAX_ACCESS_CODE_ATTEMPTS;
+use intershare_sdk::communication::initiate_receiver_communication;
+use intershare_sdk::discovery::InternalDiscovery;
+use intershare_sdk::errors::{ConnectErrors, RequestConvenienceShareErrors};
+use intershare_sdk::identity::DeviceIdentity;
+use intershare_sdk::protocol::communication::Request;
+use intershare_sdk::protocol::discovery::device_discovery_message::Content;
+use intershare_sdk::protocol::discovery::{Device, DeviceConnectionInfo, DeviceDiscoveryMessage};
+use intershare_sdk::protocol::prost::Message;
+use intershare_sdk::trust_store::trust_store;
+use intershare_sdk::{
+    InternalNearbyServer, ReceiveProgressDelegate, ReceiveProgressState, SendProgressDelegate,
+    SendProgressState, SharePolicy, TcpConnectionInfo,
+};
+use prost_stream::Stream;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod helper;
+
+#[derive(Debug)]
+struct FinishedFlag(Arc<AtomicBool>);
+
+impl ReceiveProgressDelegate for FinishedFlag {
+    fn progress_changed(&self, progress: ReceiveProgressState) {
+        if matches!(progress, ReceiveProgressState::Finished) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+struct SendProgress(Arc<Mutex<Vec<SendProgressState>>>);
+
+impl Debug for SendProgress {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        return formatter.write_str("SendProgress");
+    }
+}
+
+impl SendProgressDelegate for SendProgress {
+    fn progress_changed(&self, progress: SendProgressState) {
+        self.0.lock().unwrap().push(progress);
+    }
+}
+
+/// Makes `receiver` reachable over TCP at `port`, as if it had been discovered.
+fn discover(receiver: &Device, port: u16) {
+    let discovery = InternalDiscovery::new(None).unwrap();
+    let message = DeviceDiscoveryMessage {
+        content: Some(Content::DeviceConnectionInfo(DeviceConnectionInfo {
+            device: Some(receiver.clone()),
+            tcp: Some(TcpConnectionInfo {
+                hostname: "127.0.0.1".to_string(),
+                port: port as u32,
+            }),
+            ble: None,
+            identity_public_key: vec![],
+        })),
+    };
+
+    discovery.parse_discovery_message(message.encode_length_delimited_to_vec(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn convenience_download_over_tcp() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let shared_file = shared_dir.path().join("notes.txt");
+    fs::write(&shared_file, b"Shared over a convenience link").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+
+    let share_store = sharer
+        .share_files(
+            vec![shared_file.to_string_lossy().to_string()],
+            true,
+            Some("4711".to_string()),
+        )
+        .await;
+    let link = share_store.generate_link().expect("No link generated");
+
+    let downloader = start_downloader(download_dir.path());
+
+    assert!(matches!(
+        downloader.request_download(link.clone(), None).await,
+        Err(RequestConvenienceShareErrors::AccessCodeRequired)
+    ));
+
+    assert!(matches!(
+        downloader
+            .request_download(link.clone(), Some("0815".to_string()))
+            .await,
+        Err(RequestConvenienceShareErrors::InvalidAccessCode)
+    ));
+
+    let request = downloader
+        .request_download(link, Some("4711".to_string()))
+        .await
+        .expect("Download request failed");
+
+    let finished = Arc::new(AtomicBool::new(false));
+    let delegate = FinishedFlag(finished.clone());
+
+    let files = tokio::task::spawn_blocking(move || {
+        request.set_progress_delegate(Box::new(delegate));
+        request.accept()
+    })
+    .await
+    .unwrap()
+    .expect("Failed to receive files");
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(
+        fs::read(&files[0]).unwrap(),
+        b"Shared over a convenience link"
+    );
+    assert!(finished.load(Ordering::SeqCst));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn access_code_locks_after_wrong_attempts() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_text("Secret".to_string(), true, Some("4711".to_string()))
+        .await;
+    let link = share_store.generate_link().expect("No link generated");
+
+    let downloader = start_downloader(download_dir.path());
+
+    for _ in 0..MAX_ACCESS_CODE_ATTEMPTS {
+        assert!(matches!(
+            downloader
+                .request_download(link.clone(), Some("0815".to_string()))
+                .await,
+            Err(RequestConvenienceShareErrors::InvalidAccessCode)
+        ));
+    }
+
+    // Even the right code is refused once the share is locked
+    assert!(matches!(
+        downloader
+            .request_download(link, Some("4711".to_string()))
+            .await,
+        Err(RequestConvenienceShareErrors::AccessCodeLocked)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn concurrent_shares_can_be_revoked_individually() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+
+    let first = sharer.share_text("First".to_string(), true, None).await;
+    let second = sharer.share_text("Second".to_string(), true, None).await;
+    let not_linked = sharer
+        .share_text("Nearby only".to_string(), false, None)
+        .await;
+
+    assert_eq!(sharer.get_share_stores().await.len(), 2);
+    assert!(sharer
+        .get_share_store(not_linked.get_request_id())
+        .await
+        .is_none());
+
+    let downloader = start_downloader(download_dir.path());
+
+    assert!(sharer.revoke_share_store(first.get_request_id()).await);
+
+    assert!(matches!(
+        downloader
+            .request_download(first.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareRevoked)
+    ));
+
+    let request = downloader
+        .request_download(second.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    assert_eq!(
+        request.get_clipboard_intent().unwrap().clipboard_content,
+        "Second"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn share_policy_is_enforced() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let downloader = start_downloader(download_dir.path());
+
+    let single_use = sharer.share_text("Once".to_string(), true, None).await;
+    single_use.set_policy(SharePolicy {
+        max_downloads: Some(1),
+        ..Default::default()
+    });
+
+    // A declined download is given back once the sharer learned about it
+    downloader
+        .request_download(single_use.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed")
+        .decline();
+
+    for _ in 0..100 {
+        if single_use.get_download_count() == 0 {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(single_use.get_download_count(), 0);
+
+    let request = downloader
+        .request_download(single_use.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+    tokio::task::spawn_blocking(move || request.accept_clipboard())
+        .await
+        .unwrap()
+        .expect("Failed to receive text");
+
+    assert!(matches!(
+        downloader
+            .request_download(single_use.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareExhausted)
+    ));
+    assert_eq!(single_use.get_download_count(), 1);
+
+    let expired = sharer.share_text("Too late".to_string(), true, None).await;
+    expired.set_policy(SharePolicy {
+        expires_at: Some(1),
+        ..Default::default()
+    });
+
+    assert!(matches!(
+        downloader
+            .request_download(expired.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareExpired)
+    ));
+
+    let restricted = sharer
+        .share_text("Not for you".to_string(), true, None)
+        .await;
+    restricted.set_policy(SharePolicy {
+        allowed_device_ids: Some(vec!["another-device".to_string()]),
+        ..Default::default()
+    });
+
+    assert!(matches!(
+        downloader
+            .request_download(restricted.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::DeviceNotAllowed)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn allowed_devices_have_to_be_paired() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let paired_device = device("Downloader");
+    let downloader = InternalNearbyServer::new(
+        paired_device.clone(),
+        download_dir.path().to_string_lossy().to_string(),
+        None,
+    );
+
+    let restricted = sharer
+        .share_text("Only for you".to_string(), true, None)
+        .await;
+    restricted.set_policy(SharePolicy {
+        allowed_device_ids: Some(vec![paired_device.id.clone()]),
+        ..Default::default()
+    });
+
+    // Any device could claim the allowed id, as long as no key is pinned for it
+    assert!(matches!(
+        downloader
+            .request_download(restricted.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::DeviceNotAllowed)
+    ));
+
+    let identity = DeviceIdentity::load_or_create(Some(isolate_config_dir().to_path_buf()));
+    trust_store()
+        .write()
+        .unwrap()
+        .pin(&paired_device.id, &identity.public_key());
+
+    assert!(downloader
+        .request_download(restricted.generate_link().unwrap(), None)
+        .await
+        .is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn ended_shares_are_removed() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let downloader = start_downloader(download_dir.path());
+
+    let single_use = sharer.share_text("Once".to_string(), true, None).await;
+    single_use.set_policy(SharePolicy {
+        max_downloads: Some(1),
+        ..Default::default()
+    });
+
+    let expired = sharer.share_text("Too late".to_string(), true, None).await;
+    let cancelled = sharer
+        .share_text("Never mind".to_string(), true, None)
+        .await;
+    let remaining = sharer
+        .share_text("Still here".to_string(), true, None)
+        .await;
+
+    assert_eq!(sharer.get_share_stores().await.len(), 4);
+
+    let request = downloader
+        .request_download(single_use.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+    tokio::task::spawn_blocking(move || request.accept_clipboard())
+        .await
+        .unwrap()
+        .expect("Failed to receive text");
+
+    expired.set_policy(SharePolicy {
+        expires_at: Some(1),
+        ..Default::default()
+    });
+    cancelled.cancel(None);
+
+    // The exhausted share is kept until the sharer got the acknowledgement
+    let mut share_stores = sharer.get_share_stores().await;
+
+    for _ in 0..100 {
+        if share_stores.len() == 1 {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        share_stores = sharer.get_share_stores().await;
+    }
+
+    assert_eq!(share_stores.len(), 1);
+    assert_eq!(share_stores[0].get_request_id(), remaining.get_request_id());
+
+    // The links keep failing with the reason the share ended
+    assert!(matches!(
+        downloader
+            .request_download(single_use.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareExhausted)
+    ));
+    assert!(matches!(
+        downloader
+            .request_download(expired.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareExpired)
+    ));
+    assert!(matches!(
+        downloader
+            .request_download(cancelled.generate_link().unwrap(), None)
+            .await,
+        Err(RequestConvenienceShareErrors::ShareRevoked)
+    ));
+}
+
+fn http_get(port: u16, path: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let head = String::from_utf8_lossy(&response[..header_end]).to_string();
+
+    return (head, response[header_end + 4..].to_vec());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn http_fallback_serves_shares() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let first_file = shared_dir.path().join("first.txt");
+    let second_file = shared_dir.path().join("second.txt");
+    fs::write(&first_file, b"First file").unwrap();
+    fs::write(&second_file, b"Second file").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let port = sharer.start_http_server().await.unwrap();
+
+    let share_store = sharer
+        .share_files(
+            vec![
+                first_file.to_string_lossy().to_string(),
+                second_file.to_string_lossy().to_string(),
+            ],
+            true,
+            None,
+        )
+        .await;
+
+    share_store.set_policy(SharePolicy {
+        max_downloads: Some(1),
+        ..Default::default()
+    });
+
+    let link = url::Url::parse(&share_store.generate_http_link().unwrap()).unwrap();
+    assert_eq!(link.port(), Some(port));
+
+    let (head, page) = http_get(port, link.path());
+    assert!(head.starts_with("HTTP/1.1 200"));
+
+    let page = String::from_utf8_lossy(&page).to_string();
+    assert!(page.contains("second.txt"));
+
+    let session = page
+        .split("?session=")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap();
+
+    // Everything linked from the page belongs to the download the page counted
+    let (head, file) = http_get(
+        port,
+        &format!("{}/files/1?session={}", link.path(), session),
+    );
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert_eq!(file, b"Second file");
+
+    let (head, archive) = http_get(
+        port,
+        &format!("{}/archive.zip?session={}", link.path(), session),
+    );
+    assert!(head.starts_with("HTTP/1.1 200"));
+
+    // The archive is built once and served again
+    let (_, archive_again) = http_get(
+        port,
+        &format!("{}/archive.zip?session={}", link.path(), session),
+    );
+    assert_eq!(archive_again, archive);
+    assert_eq!(share_store.get_download_count(), 1);
+
+    let (head, _) = http_get(port, &format!("{}/files/1", link.path()));
+    assert!(head.starts_with("HTTP/1.1 410"));
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name("first.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "First file");
+    assert_eq!(archive.len(), 2);
+
+    let (head, _) = http_get(port, "/not-a-share");
+    assert!(head.starts_with("HTTP/1.1 404"));
+
+    let protected = sharer
+        .share_text("Secret".to_string(), true, Some("4711".to_string()))
+        .await;
+    assert!(protected.generate_http_link().is_none());
+
+    let (head, _) = http_get(port, &format!("/{}", protected.get_request_id()));
+    assert!(head.starts_with("HTTP/1.1 403"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn sender_fails_if_the_receiver_drops_after_the_request() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let shared_file = shared_dir.path().join("notes.txt");
+    fs::write(&shared_file, b"Never received").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], false, None)
+        .await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let receiver = device("Receiver");
+    discover(&receiver, listener.local_addr().unwrap().port());
+
+    let dropping_receiver = std::thread::spawn(move || {
+        let (tcp_stream, _) = listener.accept().unwrap();
+        let (mut encrypted_stream, _) =
+            initiate_receiver_communication(tcp_stream, &DeviceIdentity::generate()).unwrap();
+
+        // The connection goes away without a response
+        Stream::new(&mut encrypted_stream)
+            .recv::<Request>()
+            .unwrap();
+    });
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let result = share_store
+        .send_to(receiver, Some(Box::new(SendProgress(progress.clone()))))
+        .await;
+    dropping_receiver.join().unwrap();
+
+    assert!(result.is_err());
+    assert!(matches!(
+        progress.lock().unwrap().last(),
+        Some(SendProgressState::Failed)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn sender_refuses_a_receiver_with_a_changed_key() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let shared_file = shared_dir.path().join("notes.txt");
+    fs::write(&shared_file, b"Not for an impostor").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], false, None)
+        .await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let receiver = device("Receiver");
+    discover(&receiver, listener.local_addr().unwrap().port());
+    trust_store()
+        .write()
+        .unwrap()
+        .pin(&receiver.id, &DeviceIdentity::generate().public_key());
+
+    let impostor = std::thread::spawn(move || {
+        let (tcp_stream, _) = listener.accept().unwrap();
+        let (mut encrypted_stream, _) =
+            initiate_receiver_communication(tcp_stream, &DeviceIdentity::generate()).unwrap();
+
+        // Nothing arrives after the handshake
+        return Stream::new(&mut encrypted_stream)
+            .recv::<Request>()
+            .is_err();
+    });
+
+    let result = share_store.send_to(receiver, None).await;
+
+    assert!(matches!(result, Err(ConnectErrors::ReceiverKeyChanged)));
+    assert!(impostor.join().unwrap());
+}
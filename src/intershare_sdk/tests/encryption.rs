@@ -1,12 +1,73 @@
 use crate::helper::MemoryStream;
-use chacha20::cipher::StreamCipherSeek;
-use intershare_sdk::encryption::{generate_iv, generate_key, EncryptedStream};
+use intershare_sdk::communication::{
+    initiate_receiver_communication, initiate_sender_communication, transcript_hash,
+    HandshakeTranscript,
+};
+use intershare_sdk::encryption::{
+    derive_session_keys, generate_iv, generate_key, DirectionKeys, EncryptedStream, HandshakeRole,
+    SessionKeys,
+};
+use intershare_sdk::errors::{IncomingErrors, StreamIntegrityError};
+use intershare_sdk::identity::DeviceIdentity;
+use prost_stream::Stream;
+use protocol::communication::EncryptionRequest;
 use rand_core::{OsRng, RngCore};
 use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
 mod helper;
 
+/// Keys for a stream that reads back what it wrote itself.
+fn loopback_keys() -> SessionKeys {
+    let direction = DirectionKeys {
+        key: generate_key(),
+        nonce: generate_iv(),
+    };
+
+    return SessionKeys {
+        sending: direction.clone(),
+        receiving: direction,
+    };
+}
+
+fn handshake_keys() -> (SessionKeys, SessionKeys) {
+    let sender_secret = EphemeralSecret::random_from_rng(OsRng);
+    let sender_public_key = PublicKey::from(&sender_secret);
+
+    let receiver_secret = EphemeralSecret::random_from_rng(OsRng);
+    let receiver_public_key = PublicKey::from(&receiver_secret);
+
+    let iv = generate_iv();
+    let transcript = transcript_hash(&HandshakeTranscript {
+        sender_public_key: sender_public_key.as_bytes(),
+        receiver_public_key: receiver_public_key.as_bytes(),
+        iv: &iv,
+        sender_identity_key: &DeviceIdentity::generate().public_key(),
+        receiver_identity_key: &DeviceIdentity::generate().public_key(),
+        sender_capabilities: &[],
+        receiver_capabilities: &[],
+    });
+
+    let sender_keys = derive_session_keys(
+        sender_secret
+            .diffie_hellman(&receiver_public_key)
+            .as_bytes(),
+        &transcript,
+        HandshakeRole::Sender,
+    );
+    let receiver_keys = derive_session_keys(
+        receiver_secret
+            .diffie_hellman(&sender_public_key)
+            .as_bytes(),
+        &transcript,
+        HandshakeRole::Receiver,
+    );
+
+    return (sender_keys, receiver_keys);
+}
+
 #[test]
 pub fn diffie_hellman() {
     let alice_secret = EphemeralSecret::random_from_rng(OsRng);
@@ -25,11 +86,10 @@ pub fn diffie_hellman() {
 
 #[test]
 pub fn stream_encryption() {
-    let key = generate_key();
-    let nonce = generate_iv();
+    let keys = loopback_keys();
 
     let memory_stream = MemoryStream::new();
-    let mut encrypted_stream = EncryptedStream::new(key, nonce, memory_stream);
+    let mut encrypted_stream = EncryptedStream::new(keys, memory_stream);
 
     let write_data = &vec![1, 2, 3];
 
@@ -40,7 +100,6 @@ pub fn stream_encryption() {
     assert_eq!(written_bytes, 3);
 
     encrypted_stream.raw_stream.set_position(0);
-    encrypted_stream.cipher.seek(0);
 
     let mut encrypted_gibberish = Vec::new();
     encrypted_stream
@@ -57,7 +116,6 @@ pub fn stream_encryption() {
         .expect("Something went wrong, while trying to write to EncryptedStream");
 
     encrypted_stream.raw_stream.set_position(0);
-    encrypted_stream.cipher.seek(0);
 
     let mut decrypted = [0u8; 3];
     encrypted_stream
@@ -69,11 +127,10 @@ pub fn stream_encryption() {
 
 #[test]
 pub fn large_stream_encryption() {
-    let key = generate_key();
-    let nonce = generate_iv();
+    let keys = loopback_keys();
 
     let memory_stream = MemoryStream::new();
-    let mut encrypted_stream = EncryptedStream::new(key, nonce, Box::new(memory_stream));
+    let mut encrypted_stream = EncryptedStream::new(keys, Box::new(memory_stream));
 
     let mut write_data: [u8; 100000] = [0; 100000];
     let rng = &mut OsRng;
@@ -81,13 +138,11 @@ pub fn large_stream_encryption() {
 
     let write_data = write_data.as_slice();
 
-    let written_bytes = encrypted_stream
-        .write(write_data)
+    encrypted_stream
+        .write_all(write_data)
         .expect("Something went wrong, while trying to write to EncryptedStream");
 
-    assert!(written_bytes > 0);
     encrypted_stream.raw_stream.set_position(0);
-    encrypted_stream.cipher.seek(0);
 
     let mut encrypted_gibberish = Vec::new();
     encrypted_stream
@@ -97,12 +152,248 @@ pub fn large_stream_encryption() {
 
     assert_ne!(write_data, &encrypted_gibberish);
     encrypted_stream.raw_stream.set_position(0);
-    encrypted_stream.cipher.seek(0);
 
     let mut decrypted_buffer: [u8; 100000] = [0; 100000];
-    let read_bytes = encrypted_stream
-        .read(&mut decrypted_buffer)
+    encrypted_stream
+        .read_exact(&mut decrypted_buffer)
         .expect("Something went wrong, while trying to decrypt the stream");
 
-    assert_eq!(write_data, &decrypted_buffer[..read_bytes]);
+    assert_eq!(write_data, &decrypted_buffer);
+}
+
+#[test]
+pub fn tampered_record_is_rejected() {
+    let keys = loopback_keys();
+
+    let mut encrypted_stream = EncryptedStream::new(keys.clone(), MemoryStream::new());
+
+    encrypted_stream
+        .write_all(&[1, 2, 3, 4, 5])
+        .expect("Failed to write to EncryptedStream");
+
+    // Flip a bit inside the ciphertext, after the 4 byte record header
+    encrypted_stream.raw_stream.set_position(0);
+    let mut raw = Vec::new();
+    encrypted_stream
+        .raw_stream
+        .read_to_end(&mut raw)
+        .expect("Error reading memory_stream");
+    raw[5] ^= 0x01;
+
+    let mut tampered_stream = EncryptedStream::new(keys, MemoryStream::new());
+    tampered_stream
+        .raw_stream
+        .write_all(&raw)
+        .expect("Failed to write memory_stream");
+    tampered_stream.raw_stream.set_position(0);
+
+    let mut decrypted = [0u8; 5];
+    assert!(tampered_stream.read_exact(&mut decrypted).is_err());
+    assert_eq!(
+        tampered_stream.integrity_error(),
+        Some(StreamIntegrityError::Tampered)
+    );
+}
+
+#[test]
+pub fn truncated_stream_is_rejected() {
+    let keys = loopback_keys();
+
+    let mut encrypted_stream = EncryptedStream::new(keys, MemoryStream::new());
+
+    encrypted_stream
+        .write_all(&[1, 2, 3])
+        .expect("Failed to write to EncryptedStream");
+
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut decrypted = Vec::new();
+    assert!(encrypted_stream.read_to_end(&mut decrypted).is_err());
+    assert_eq!(
+        encrypted_stream.integrity_error(),
+        Some(StreamIntegrityError::Truncated)
+    );
+}
+
+#[test]
+pub fn finished_stream_ends_cleanly() {
+    let keys = loopback_keys();
+
+    let mut encrypted_stream = EncryptedStream::new(keys, MemoryStream::new());
+
+    encrypted_stream
+        .write_all(&[1, 2, 3])
+        .expect("Failed to write to EncryptedStream");
+    encrypted_stream
+        .finish()
+        .expect("Failed to finish EncryptedStream");
+
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut decrypted = Vec::new();
+    encrypted_stream
+        .read_to_end(&mut decrypted)
+        .expect("Failed to read finished stream");
+
+    assert_eq!(decrypted, vec![1, 2, 3]);
+    assert!(encrypted_stream.integrity_error().is_none());
+}
+
+#[test]
+pub fn aborted_stream_carries_message() {
+    let keys = loopback_keys();
+
+    let mut encrypted_stream = EncryptedStream::new(keys, MemoryStream::new());
+
+    encrypted_stream
+        .write_all(&[1, 2, 3])
+        .expect("Failed to write to EncryptedStream");
+    encrypted_stream
+        .abort(b"Cancelled")
+        .expect("Failed to abort EncryptedStream");
+
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut decrypted = Vec::new();
+    assert!(encrypted_stream.read_to_end(&mut decrypted).is_err());
+    assert_eq!(decrypted, vec![1, 2, 3]);
+    assert_eq!(
+        encrypted_stream.abort_message(),
+        Some(b"Cancelled".to_vec())
+    );
+    assert!(encrypted_stream.integrity_error().is_none());
+}
+
+#[test]
+pub fn session_keys_are_direction_specific() {
+    let (sender_keys, receiver_keys) = handshake_keys();
+
+    assert_eq!(sender_keys.sending, receiver_keys.receiving);
+    assert_eq!(sender_keys.receiving, receiver_keys.sending);
+
+    assert_ne!(sender_keys.sending.key, sender_keys.receiving.key);
+    assert_ne!(sender_keys.sending.nonce, sender_keys.receiving.nonce);
+}
+
+#[test]
+pub fn directions_never_share_keystream() {
+    let (sender_keys, receiver_keys) = handshake_keys();
+
+    let mut sender_stream = EncryptedStream::new(sender_keys, MemoryStream::new());
+    let mut receiver_stream = EncryptedStream::new(receiver_keys, MemoryStream::new());
+
+    // Both peers send the exact same plaintext
+    let plaintext = [0x42u8; 1024];
+    sender_stream
+        .write_all(&plaintext)
+        .expect("Failed to write sender stream");
+    receiver_stream
+        .write_all(&plaintext)
+        .expect("Failed to write receiver stream");
+
+    sender_stream.raw_stream.set_position(0);
+    receiver_stream.raw_stream.set_position(0);
+
+    let mut sender_ciphertext = Vec::new();
+    sender_stream
+        .raw_stream
+        .read_to_end(&mut sender_ciphertext)
+        .expect("Error reading memory_stream");
+
+    let mut receiver_ciphertext = Vec::new();
+    receiver_stream
+        .raw_stream
+        .read_to_end(&mut receiver_ciphertext)
+        .expect("Error reading memory_stream");
+
+    assert_eq!(sender_ciphertext.len(), receiver_ciphertext.len());
+
+    // With a shared keystream, the XOR of both ciphertexts would equal the XOR of
+    // the plaintexts, which is all zero here.
+    let identical_bytes = sender_ciphertext[4..]
+        .iter()
+        .zip(receiver_ciphertext[4..].iter())
+        .filter(|(sender_byte, receiver_byte)| sender_byte == receiver_byte)
+        .count();
+
+    assert!(identical_bytes < 32);
+}
+
+#[tokio::test]
+pub async fn handshake_derives_matching_directions() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let address = listener.local_addr().expect("Failed to get local address");
+
+    let sender_identity = DeviceIdentity::generate();
+    let receiver_identity = DeviceIdentity::generate();
+    let sender_identity_key = sender_identity.public_key();
+    let receiver_identity_key = receiver_identity.public_key();
+
+    let receiver = thread::spawn(move || {
+        let (tcp_stream, _) = listener.accept().expect("Failed to accept connection");
+        let (mut encrypted_stream, session) =
+            initiate_receiver_communication(tcp_stream, &receiver_identity)
+                .expect("Receiver handshake failed");
+
+        assert_eq!(session.peer_identity_key, sender_identity_key);
+
+        let mut request = [0u8; 7];
+        encrypted_stream
+            .read_exact(&mut request)
+            .expect("Failed to read request");
+        assert_eq!(&request, b"request");
+
+        encrypted_stream
+            .write_all(b"response")
+            .expect("Failed to write response");
+
+        session.short_authentication_string()
+    });
+
+    let tcp_stream = TcpStream::connect(address).expect("Failed to connect");
+    let (mut encrypted_stream, session) =
+        initiate_sender_communication(tcp_stream, &sender_identity)
+            .await
+            .expect("Sender handshake failed");
+
+    assert_eq!(session.peer_identity_key, receiver_identity_key);
+
+    encrypted_stream
+        .write_all(b"request")
+        .expect("Failed to write request");
+
+    let mut response = [0u8; 8];
+    encrypted_stream
+        .read_exact(&mut response)
+        .expect("Failed to read response");
+    assert_eq!(&response, b"response");
+
+    let receiver_sas = receiver.join().expect("Receiver thread panicked");
+    assert_eq!(session.short_authentication_string(), receiver_sas);
+    assert_eq!(receiver_sas.len(), 6);
+}
+
+#[test]
+pub fn legacy_sender_is_rejected_without_response() {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let mut memory_stream = MemoryStream::new();
+    Stream::new(&mut memory_stream)
+        .send(&EncryptionRequest {
+            public_key: PublicKey::from(&secret).as_bytes().to_vec(),
+            ..Default::default()
+        })
+        .expect("Failed to write request");
+
+    let request_length = memory_stream.position();
+    memory_stream.set_position(0);
+
+    let error = initiate_receiver_communication(&mut memory_stream, &DeviceIdentity::generate())
+        .err()
+        .expect("Legacy sender was accepted");
+
+    assert_eq!(
+        error.to_string(),
+        IncomingErrors::LegacyProtocolVersion.to_string()
+    );
+    assert_eq!(memory_stream.position(), request_length);
 }
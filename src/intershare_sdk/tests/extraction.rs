@@ -0,0 +1,503 @@
+use crate::helper::MemoryStream;
+use intershare_sdk::encryption::{
+    generate_iv, generate_key, DirectionKeys, EncryptedReadWrite, EncryptedStream, SessionKeys,
+};
+use intershare_sdk::errors::{
+    DeclaredSizeExceeded, MissingDigests, UndeclaredEntry, UnsafeArchiveError,
+};
+use intershare_sdk::protocol::communication::capabilities::Compression;
+use intershare_sdk::protocol::communication::FileDigests;
+use intershare_sdk::protocol::prost::Message;
+use intershare_sdk::test_utils::{untar_stream, DeclaredSizes, UnpackedTransfer};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+use tar::{EntryType, Header};
+
+mod helper;
+
+/// Appends a raw entry, without the path checks `tar::Builder` does.
+fn push_entry(archive: &mut Vec<u8>, path: &[u8], entry_type: EntryType, link: &[u8], data: &[u8]) {
+    if path.len() > 99 {
+        let long_name = [path, b"\0"].concat();
+        push_entry(
+            archive,
+            b"././@LongLink",
+            EntryType::GNULongName,
+            b"",
+            &long_name,
+        );
+    }
+
+    let name = &path[..path.len().min(99)];
+    let link = &link[..link.len().min(99)];
+
+    let mut header = Header::new_gnu();
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.as_old_mut().linkname[..link.len()].copy_from_slice(link);
+    header.set_entry_type(entry_type);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+
+    archive.extend_from_slice(header.as_bytes());
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+}
+
+fn file(archive: &mut Vec<u8>, path: &str, data: &[u8]) {
+    push_entry(archive, path.as_bytes(), EntryType::Regular, b"", data);
+}
+
+fn link(archive: &mut Vec<u8>, path: &str, entry_type: EntryType, target: &str) {
+    push_entry(archive, path.as_bytes(), entry_type, target.as_bytes(), b"");
+}
+
+/// Path of the entry as it is announced in the manifest.
+fn manifest_path(entry: &tar::Entry<&[u8]>) -> String {
+    let path: Vec<String> = entry
+        .path()
+        .unwrap()
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+
+    return path.join("/");
+}
+
+/// Declares every entry of the archive with its size, like a manifest would.
+fn declare_all(archive: &[u8], total: u64) -> DeclaredSizes {
+    let mut files = HashMap::new();
+    let mut entries = tar::Archive::new(archive);
+
+    for entry in entries.entries().unwrap() {
+        let entry = entry.unwrap();
+        files.insert(manifest_path(&entry), entry.header().size().unwrap());
+    }
+
+    return DeclaredSizes { total, files };
+}
+
+/// Appends the digests trailer for the files in the archive so far, like the sender does.
+fn push_digests(archive: &mut Vec<u8>) {
+    let digests = digests_of(archive);
+    push_entry(
+        archive,
+        b".intershare-digests",
+        EntryType::new(b'I'),
+        b"",
+        &digests,
+    );
+}
+
+fn digests_of(archive: &[u8]) -> Vec<u8> {
+    let mut digests = FileDigests::default();
+    let mut entries = tar::Archive::new(archive);
+
+    for entry in entries.entries().unwrap() {
+        let mut entry = entry.unwrap();
+
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+
+        let path = manifest_path(&entry);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        digests.sha256.insert(path, Sha256::digest(&data).to_vec());
+    }
+
+    return digests.encode_to_vec();
+}
+
+/// Sends the archive through an encrypted stream into `dest_dir`, like a transfer would.
+fn unpack(archive: Vec<u8>, dest_dir: &Path, declared_total: u64) -> io::Result<UnpackedTransfer> {
+    let declared = declare_all(&archive, declared_total);
+    return unpack_declared(archive, dest_dir, &declared);
+}
+
+fn unpack_declared(
+    mut archive: Vec<u8>,
+    dest_dir: &Path,
+    declared: &DeclaredSizes,
+) -> io::Result<UnpackedTransfer> {
+    push_digests(&mut archive);
+    return unpack_without_digests(archive, dest_dir, declared);
+}
+
+/// Like `unpack_declared`, but the archive is sent as is.
+fn unpack_without_digests(
+    mut archive: Vec<u8>,
+    dest_dir: &Path,
+    declared: &DeclaredSizes,
+) -> io::Result<UnpackedTransfer> {
+    archive.extend_from_slice(&[0; 1024]);
+
+    let direction = DirectionKeys {
+        key: generate_key(),
+        nonce: generate_iv(),
+    };
+    let keys = SessionKeys {
+        sending: direction.clone(),
+        receiving: direction,
+    };
+
+    let mut encrypted_stream = EncryptedStream::new(keys, MemoryStream::new());
+    encrypted_stream.write_all(&archive).unwrap();
+    encrypted_stream.finish().unwrap();
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut stream: Box<dyn EncryptedReadWrite> = Box::new(encrypted_stream);
+
+    return untar_stream(&mut stream, dest_dir, Compression::None, declared);
+}
+
+fn unsafe_archive_error(error: &io::Error) -> Option<&UnsafeArchiveError> {
+    return error.get_ref()?.downcast_ref::<UnsafeArchiveError>();
+}
+
+/// Every path below `directory`, without following links.
+fn walk(directory: &Path) -> Vec<String> {
+    let mut paths: Vec<String> = walkdir::WalkDir::new(directory)
+        .min_depth(1)
+        .into_iter()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let path = entry.path().strip_prefix(directory).unwrap();
+            path.to_string_lossy().replace('\\', "/")
+        })
+        .collect();
+
+    paths.sort();
+
+    return paths;
+}
+
+#[test]
+fn traversal_stays_inside_destination() {
+    let parent = tempfile::tempdir().unwrap();
+    let dest_dir = parent.path().join("dest");
+    fs::create_dir(&dest_dir).unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "../escaped.txt", b"1");
+    file(&mut archive, "/absolute.txt", b"2");
+    file(&mut archive, "share/../../nested.txt", b"3");
+
+    let unpacked = unpack(archive, &dest_dir, u64::MAX).unwrap();
+
+    assert_eq!(unpacked.files.len(), 3);
+    assert!(unpacked
+        .files
+        .iter()
+        .all(|path| Path::new(path).starts_with(&dest_dir)));
+    assert_eq!(walk(parent.path()).len(), 5);
+    assert_eq!(fs::read(dest_dir.join("escaped.txt")).unwrap(), b"1");
+}
+
+#[test]
+fn names_are_portable() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "CON.txt", b"");
+    file(&mut archive, "share/aux", b"");
+    file(&mut archive, "share/trailing. .", b"");
+    file(&mut archive, "share/a<b>:c.txt", b"");
+    // "é" decomposed into "e" and a combining acute accent
+    file(&mut archive, "share/cafe\u{301}.txt", b"");
+
+    unpack(archive, dest_dir.path(), u64::MAX).unwrap();
+
+    assert_eq!(
+        walk(dest_dir.path()),
+        vec![
+            "_CON.txt",
+            "share",
+            "share/_aux",
+            "share/a_b__c.txt",
+            "share/café.txt",
+            "share/trailing",
+        ]
+    );
+}
+
+#[test]
+fn entries_with_the_same_name_are_kept_apart() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "a.txt", b"1");
+    file(&mut archive, "a.txt", b"2");
+
+    unpack(archive, dest_dir.path(), u64::MAX).unwrap();
+
+    assert_eq!(walk(dest_dir.path()), vec!["a (1).txt", "a.txt"]);
+    assert_eq!(fs::read(dest_dir.path().join("a (1).txt")).unwrap(), b"2");
+}
+
+#[test]
+fn oversized_paths_are_rejected() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, &format!("share/{}", "a".repeat(300)), b"");
+
+    let error = unpack(archive, dest_dir.path(), u64::MAX).unwrap_err();
+    assert!(matches!(
+        unsafe_archive_error(&error),
+        Some(UnsafeArchiveError::PathTooLong { .. })
+    ));
+
+    let mut archive = Vec::new();
+    file(&mut archive, &"share/".repeat(1000), b"");
+
+    let error = unpack(archive, dest_dir.path(), u64::MAX).unwrap_err();
+    assert!(matches!(
+        unsafe_archive_error(&error),
+        Some(UnsafeArchiveError::PathTooLong { .. })
+    ));
+}
+
+#[test]
+fn exceeding_the_declared_size_is_rejected() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "first.bin", &[0; 8]);
+    file(&mut archive, "second.bin", &[0; 8]);
+
+    let error = unpack(archive, dest_dir.path(), 10).unwrap_err();
+    assert!(error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<DeclaredSizeExceeded>()));
+    // Nothing is moved into place if the transfer fails
+    assert!(walk(dest_dir.path()).is_empty());
+}
+
+#[test]
+fn undeclared_entries_are_rejected() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "selected.txt", b"1");
+    let declared = declare_all(&archive, u64::MAX);
+    file(&mut archive, "unselected.txt", b"2");
+
+    let error = unpack_declared(archive, dest_dir.path(), &declared).unwrap_err();
+    assert!(error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<UndeclaredEntry>())
+        .is_some_and(|undeclared| undeclared.path == "unselected.txt"));
+    assert!(walk(dest_dir.path()).is_empty());
+}
+
+#[test]
+fn transfers_without_digests_are_rejected() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "file.txt", b"content");
+    let declared = declare_all(&archive, u64::MAX);
+
+    let error = unpack_without_digests(archive, dest_dir.path(), &declared).unwrap_err();
+    assert!(error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<MissingDigests>()));
+    assert!(walk(dest_dir.path()).is_empty());
+}
+
+#[test]
+fn files_without_a_digest_fail() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "digested.txt", b"1");
+    push_digests(&mut archive);
+    file(&mut archive, "undigested.txt", b"2");
+    let declared = declare_all(&archive, u64::MAX);
+
+    let unpacked = unpack_without_digests(archive, dest_dir.path(), &declared).unwrap();
+
+    assert_eq!(
+        unpacked.failed_files,
+        vec![dest_dir
+            .path()
+            .join("undigested.txt")
+            .to_string_lossy()
+            .to_string()]
+    );
+    assert_eq!(walk(dest_dir.path()), vec!["digested.txt"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn links_are_confined_to_their_folder() {
+    let parent = tempfile::tempdir().unwrap();
+    let dest_dir = parent.path().join("dest");
+    fs::create_dir(&dest_dir).unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, "share/file.txt", b"content");
+    link(&mut archive, "share/inside", EntryType::Symlink, "file.txt");
+    link(
+        &mut archive,
+        "share/sub/up",
+        EntryType::Symlink,
+        "../file.txt",
+    );
+    link(
+        &mut archive,
+        "share/escape",
+        EntryType::Symlink,
+        "../../outside",
+    );
+    link(
+        &mut archive,
+        "share/absolute",
+        EntryType::Symlink,
+        "/etc/passwd",
+    );
+    link(&mut archive, "share/hard", EntryType::Link, "/etc/passwd");
+    link(&mut archive, "top", EntryType::Symlink, "share");
+
+    unpack(archive, &dest_dir, u64::MAX).unwrap();
+
+    assert_eq!(
+        walk(&dest_dir),
+        vec![
+            "share",
+            "share/file.txt",
+            "share/inside",
+            "share/sub",
+            "share/sub/up"
+        ]
+    );
+    assert_eq!(fs::read(dest_dir.join("share/sub/up")).unwrap(), b"content");
+}
+
+#[cfg(unix)]
+#[test]
+fn writing_through_links_is_rejected() {
+    let parent = tempfile::tempdir().unwrap();
+    let dest_dir = parent.path().join("dest");
+    fs::create_dir(&dest_dir).unwrap();
+
+    // Each link stays inside "share" on its own, but "share/up" points to "share/.." once resolved
+    let mut archive = Vec::new();
+    push_entry(&mut archive, b"share/a", EntryType::Directory, b"", b"");
+    link(&mut archive, "share/a/b", EntryType::Symlink, "..");
+    link(&mut archive, "share/up", EntryType::Symlink, "a/b/..");
+    file(&mut archive, "share/up/escaped.txt", b"");
+
+    let error = unpack(archive, &dest_dir, u64::MAX).unwrap_err();
+    assert!(matches!(
+        unsafe_archive_error(&error),
+        Some(UnsafeArchiveError::OutsideDestination { .. })
+    ));
+    assert!(!dest_dir.join("escaped.txt").exists());
+}
+
+#[test]
+fn journal_folder_is_reserved() {
+    let dest_dir = tempfile::tempdir().unwrap();
+
+    let mut archive = Vec::new();
+    file(&mut archive, ".intershare/journal/forged.json", b"{}");
+
+    let error = unpack(archive, dest_dir.path(), u64::MAX).unwrap_err();
+    assert!(matches!(
+        unsafe_archive_error(&error),
+        Some(UnsafeArchiveError::OutsideDestination { .. })
+    ));
+    assert!(walk(dest_dir.path()).is_empty());
+}
+
+/// Small deterministic generator, so failures can be reproduced from the seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+
+    fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+        return values[(self.next() % values.len() as u64) as usize];
+    }
+}
+
+const COMPONENTS: [&str; 12] = [
+    "share", "share", "a", "b", "..", ".", "", "CON", "e\u{301}", "name.", "/", "x:y",
+];
+
+fn random_path(random: &mut XorShift) -> String {
+    let length = 1 + random.next() % 5;
+
+    return (0..length)
+        .map(|_| random.pick(&COMPONENTS))
+        .collect::<Vec<&str>>()
+        .join("/");
+}
+
+#[test]
+fn random_archives_stay_inside_destination() {
+    for seed in 1..=300u64 {
+        let parent = tempfile::tempdir().unwrap();
+        let dest_dir = parent.path().join("dest");
+        let sibling = parent.path().join("sibling");
+        fs::create_dir(&dest_dir).unwrap();
+        fs::create_dir(&sibling).unwrap();
+
+        let mut random = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut archive = Vec::new();
+
+        for _ in 0..1 + random.next() % 12 {
+            let path = random_path(&mut random);
+
+            match random.next() % 4 {
+                0 => push_entry(
+                    &mut archive,
+                    path.as_bytes(),
+                    EntryType::Directory,
+                    b"",
+                    b"",
+                ),
+                1 => link(
+                    &mut archive,
+                    &path,
+                    EntryType::Symlink,
+                    &random_path(&mut random),
+                ),
+                2 => link(
+                    &mut archive,
+                    &path,
+                    EntryType::Link,
+                    &random_path(&mut random),
+                ),
+                _ => file(&mut archive, &path, seed.to_string().as_bytes()),
+            }
+        }
+
+        // Errors are fine, as long as nothing ends up outside of the destination
+        if let Ok(unpacked) = unpack(archive, &dest_dir, u64::MAX) {
+            for path in &unpacked.files {
+                assert!(Path::new(path).starts_with(&dest_dir), "seed {}", seed);
+            }
+        }
+
+        let outside: Vec<String> = walk(parent.path())
+            .into_iter()
+            .filter(|path| !path.starts_with("dest"))
+            .collect();
+        assert_eq!(outside, vec!["sibling"], "seed {}", seed);
+    }
+}
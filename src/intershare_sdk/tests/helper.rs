@@ -1,4 +1,13 @@
+#![allow(dead_code)]
+
+use intershare_sdk::protocol::discovery::Device;
+use intershare_sdk::stream::Close;
+use intershare_sdk::{
+    set_config_dir, ConnectionRequest, InternalNearbyServer, NearbyConnectionDelegate,
+};
 use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
 pub struct MemoryStream {
     last_written_byte_length: usize,
@@ -38,6 +47,10 @@ impl Write for MemoryStream {
     }
 }
 
+impl Close for MemoryStream {
+    fn close(&self) {}
+}
+
 impl Read for MemoryStream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.last_written_byte_length = 0;
@@ -45,6 +58,61 @@ impl Read for MemoryStream {
     }
 }
 
+#[derive(Debug)]
+pub struct IgnoreConnectionRequests;
+
+impl NearbyConnectionDelegate for IgnoreConnectionRequests {
+    fn received_connection_request(&self, _request: Arc<ConnectionRequest>) {}
+}
+
+pub fn device(name: &str) -> Device {
+    return Device {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        device_type: 0,
+        protocol_version: None,
+        min_protocol_version: None,
+    };
+}
+
+/// Keeps identities and pinned keys of the test devices out of the user's config directory.
+/// The directory is set once per test binary, before the first server is created.
+pub fn isolate_config_dir() -> &'static Path {
+    static CONFIG_DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+    let config_dir = CONFIG_DIR.get_or_init(|| {
+        let config_dir = tempfile::tempdir().unwrap();
+        set_config_dir(config_dir.path().to_string_lossy().to_string());
+
+        return config_dir;
+    });
+
+    return config_dir.path();
+}
+
+pub async fn start_sharer(shared_dir: &Path) -> InternalNearbyServer {
+    isolate_config_dir();
+
+    let sharer = InternalNearbyServer::new(
+        device("Sharer"),
+        shared_dir.to_string_lossy().to_string(),
+        Some(Box::new(IgnoreConnectionRequests)),
+    );
+    sharer.start().await;
+
+    return sharer;
+}
+
+pub fn start_downloader(download_dir: &Path) -> InternalNearbyServer {
+    isolate_config_dir();
+
+    return InternalNearbyServer::new(
+        device("Downloader"),
+        download_dir.to_string_lossy().to_string(),
+        None,
+    );
+}
+
 #[test]
 pub fn memory_stream() {
     let mut memory_stream = MemoryStream::new();
@@ -0,0 +1,41 @@
+use intershare_sdk::manifest::{build_manifest, total_size, ManifestEntry, ManifestEntryKind};
+use std::fs;
+
+#[test]
+fn manifest_lists_nested_entries() {
+    let directory = tempfile::tempdir().unwrap();
+    let shared = directory.path().join("Photos");
+    fs::create_dir_all(shared.join("2024")).unwrap();
+    fs::write(shared.join("2024/beach.jpg"), [0u8; 42]).unwrap();
+    fs::write(directory.path().join("notes.txt"), "hello").unwrap();
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(shared.join("2024/beach.jpg"), shared.join("latest.jpg")).unwrap();
+
+    let manifest = build_manifest(&[
+        shared.to_string_lossy().to_string(),
+        directory
+            .path()
+            .join("notes.txt")
+            .to_string_lossy()
+            .to_string(),
+    ])
+    .unwrap();
+
+    assert_eq!(total_size(&manifest), 47);
+
+    let entries: Vec<ManifestEntry> = manifest.into_iter().map(ManifestEntry::from).collect();
+    let entry = |path: &str| entries.iter().find(|entry| entry.path == path).unwrap();
+
+    assert_eq!(entry("Photos").kind, ManifestEntryKind::Directory);
+    assert_eq!(entry("Photos/2024/beach.jpg").size, 42);
+    assert_eq!(
+        entry("Photos/2024/beach.jpg").mime_type.as_deref(),
+        Some("image/jpeg")
+    );
+    assert_eq!(entry("notes.txt").kind, ManifestEntryKind::File);
+    assert!(entry("notes.txt").modified_at.is_some());
+
+    #[cfg(unix)]
+    assert_eq!(entry("Photos/latest.jpg").kind, ManifestEntryKind::Symlink);
+}
@@ -0,0 +1,925 @@
+use crate::helper::{device, start_downloader, start_sharer};
+use intershare_sdk::clipboard::MAX_CLIPBOARD_SIZE;
+use intershare_sdk::errors::ReceiveErrors;
+use intershare_sdk::stream::{NativeReadDelegate, NativeWriteDelegate};
+use intershare_sdk::{
+    ClipboardItem, CollisionPolicy, ConnectionRequest, FolderLayout, ReceiveLimits, ReceivePolicy,
+    ReceiveProgressDelegate, ReceiveProgressState, ShareStore, SharedData, SpaceCheck,
+};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::io::Read;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+
+mod helper;
+
+struct CancelHalfway(Weak<ConnectionRequest>);
+
+impl Debug for CancelHalfway {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        return formatter.write_str("CancelHalfway");
+    }
+}
+
+impl ReceiveProgressDelegate for CancelHalfway {
+    fn progress_changed(&self, progress: ReceiveProgressState) {
+        if let ReceiveProgressState::Receiving { progress } = progress {
+            if let Some(request) = self.0.upgrade().filter(|_| progress > 0.5) {
+                request.cancel();
+            }
+        }
+    }
+}
+
+struct StopSharing(Arc<ShareStore>);
+
+impl Debug for StopSharing {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        return formatter.write_str("StopSharing");
+    }
+}
+
+impl ReceiveProgressDelegate for StopSharing {
+    fn progress_changed(&self, progress: ReceiveProgressState) {
+        if matches!(progress, ReceiveProgressState::Receiving { progress } if progress > 0.1) {
+            self.0.cancel(Some("Stopped sharing".to_string()));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MemoryReader(Vec<u8>);
+
+impl NativeReadDelegate for MemoryReader {
+    fn read(&self, offset: u64, buffer_length: u64) -> Vec<u8> {
+        let start = (offset as usize).min(self.0.len());
+        let end = (start + buffer_length as usize).min(self.0.len());
+
+        return self.0[start..end].to_vec();
+    }
+}
+
+/// Received files as (path, content) pairs.
+type ReceivedFiles = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+#[derive(Debug)]
+struct MemorySink(ReceivedFiles);
+
+impl NativeWriteDelegate for MemorySink {
+    fn create_file(&self, path: String, _size: u64) -> Option<String> {
+        self.0.lock().unwrap().push((path.clone(), vec![]));
+        return Some(format!("memory://{}", path));
+    }
+
+    fn write(&self, data: Vec<u8>) -> u64 {
+        let mut files = self.0.lock().unwrap();
+        let (_, content) = files.last_mut().unwrap();
+        content.extend_from_slice(&data);
+
+        return data.len() as u64;
+    }
+
+    fn finish(&self) {}
+
+    fn abort(&self) {
+        self.0.lock().unwrap().pop();
+    }
+}
+
+/// Forwards the first connection to the share behind `link`, and drops it once the sharer
+/// sent `limit` bytes. Returns the link pointing to the proxy.
+fn drop_connection_after(link: &str, limit: u64) -> String {
+    let mut url = url::Url::parse(link).unwrap();
+    let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let sharer_address = format!("{}:{}", query["ip"], query["p"]);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy_port = listener.local_addr().unwrap().port();
+
+    std::thread::spawn(move || {
+        let (mut downloader, _) = listener.accept().unwrap();
+        let mut sharer = TcpStream::connect(sharer_address).unwrap();
+
+        let mut downloader_reader = downloader.try_clone().unwrap();
+        let mut sharer_writer = sharer.try_clone().unwrap();
+        std::thread::spawn(move || std::io::copy(&mut downloader_reader, &mut sharer_writer));
+
+        let _ = std::io::copy(&mut (&mut sharer).take(limit), &mut downloader);
+        let _ = downloader.shutdown(Shutdown::Both);
+        let _ = sharer.shutdown(Shutdown::Both);
+    });
+
+    url.query_pairs_mut().clear().extend_pairs(query.iter().map(
+        |(key, value)| match key.as_str() {
+            "ip" => (key.clone(), "127.0.0.1".to_string()),
+            "p" => (key.clone(), proxy_port.to_string()),
+            _ => (key.clone(), value.clone()),
+        },
+    ));
+
+    return url.to_string();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn receiver_can_select_files() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let project = shared_dir.path().join("project");
+    fs::create_dir_all(project.join("build")).unwrap();
+    fs::write(project.join("build/artifact.bin"), [0u8; 4096]).unwrap();
+    fs::write(project.join("report.pdf"), b"%PDF").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![project.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let manifest = request.get_manifest();
+    let report = manifest
+        .iter()
+        .position(|entry| entry.path == "project/report.pdf")
+        .unwrap() as u32;
+
+    let files = tokio::task::spawn_blocking(move || {
+        assert!(request.accept_files(vec![manifest.len() as u32]).is_err());
+        request.accept_files(vec![report])
+    })
+    .await
+    .unwrap()
+    .expect("Failed to receive files");
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(fs::read(&files[0]).unwrap(), b"%PDF");
+    assert!(!download_dir.path().join("project/build").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn interrupted_download_is_resumed() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let content: Vec<u8> = (0..8 * 1024 * 1024)
+        .map(|index| (index % 251) as u8)
+        .collect();
+    let shared_file = shared_dir.path().join("video.mp4");
+    fs::write(&shared_file, &content).unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let link = drop_connection_after(&share_store.generate_link().unwrap(), 4 * 1024 * 1024);
+    let request = downloader
+        .request_download(link, None)
+        .await
+        .expect("Download request failed");
+
+    let interrupted = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap();
+
+    assert!(matches!(interrupted, Err(ReceiveErrors::StreamTruncated)));
+
+    // The received part is kept for the next attempt, but not in the destination
+    let partial_file = download_dir.path().join("video.mp4");
+    assert!(!partial_file.exists());
+
+    let staged_length: u64 = walkdir::WalkDir::new(download_dir.path())
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_name() == "video.mp4")
+        .map(|entry| entry.metadata().unwrap().len())
+        .sum();
+    assert!(staged_length > 0 && staged_length < content.len() as u64);
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let files = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to resume");
+
+    assert_eq!(files, vec![partial_file.to_string_lossy().to_string()]);
+    assert!(fs::read(&partial_file).unwrap() == content);
+    assert_eq!(fs::read_dir(download_dir.path()).unwrap().count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn files_from_an_earlier_attempt_are_verified() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let intro: Vec<u8> = (0..1024 * 1024).map(|index| (index % 241) as u8).collect();
+    let content: Vec<u8> = (0..8 * 1024 * 1024)
+        .map(|index| (index % 251) as u8)
+        .collect();
+    let intro_file = shared_dir.path().join("intro.mp4");
+    let shared_file = shared_dir.path().join("video.mp4");
+    fs::write(&intro_file, &intro).unwrap();
+    fs::write(&shared_file, &content).unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(
+            vec![
+                intro_file.to_string_lossy().to_string(),
+                shared_file.to_string_lossy().to_string(),
+            ],
+            true,
+            None,
+        )
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let link = drop_connection_after(&share_store.generate_link().unwrap(), 4 * 1024 * 1024);
+    let request = downloader
+        .request_download(link, None)
+        .await
+        .expect("Download request failed");
+
+    let interrupted = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap();
+
+    assert!(matches!(interrupted, Err(ReceiveErrors::StreamTruncated)));
+
+    // The intro was completed by the first attempt, and changes while it is staged
+    let staged_intro = walkdir::WalkDir::new(download_dir.path())
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .find(|entry| entry.file_name() == "intro.mp4")
+        .unwrap();
+    fs::write(staged_intro.path(), vec![0u8; intro.len()]).unwrap();
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let resumed = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap();
+
+    let intro_path = download_dir.path().join("intro.mp4");
+    assert!(matches!(
+        resumed,
+        Err(ReceiveErrors::IntegrityCheckFailed { file_paths })
+            if file_paths == vec![intro_path.to_string_lossy().to_string()]
+    ));
+    assert!(!intro_path.exists());
+    assert!(fs::read(download_dir.path().join("video.mp4")).unwrap() == content);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn journals_pointing_outside_are_not_resumed() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+    let outside_dir = tempfile::tempdir().unwrap();
+
+    let content: Vec<u8> = (0..8 * 1024 * 1024)
+        .map(|index| (index % 251) as u8)
+        .collect();
+    let shared_file = shared_dir.path().join("video.mp4");
+    fs::write(&shared_file, &content).unwrap();
+
+    let outside_file = outside_dir.path().join("video.mp4");
+    fs::write(&outside_file, b"Not part of the transfer").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let link = drop_connection_after(&share_store.generate_link().unwrap(), 4 * 1024 * 1024);
+    let request = downloader
+        .request_download(link, None)
+        .await
+        .expect("Download request failed");
+
+    let interrupted = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap();
+
+    assert!(matches!(interrupted, Err(ReceiveErrors::StreamTruncated)));
+
+    // The journal is changed to continue writing a file outside of the staging directory
+    let entries: Vec<walkdir::DirEntry> = walkdir::WalkDir::new(download_dir.path())
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .collect();
+    let staged_file = entries
+        .iter()
+        .find(|entry| entry.file_name() == "video.mp4")
+        .unwrap();
+    let journal = entries
+        .iter()
+        .find(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "journal")
+        })
+        .unwrap();
+
+    let journaled = fs::read_to_string(journal.path()).unwrap().replace(
+        &*staged_file.path().to_string_lossy(),
+        &outside_file.to_string_lossy(),
+    );
+    fs::write(journal.path(), journaled).unwrap();
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to receive files");
+
+    assert!(fs::read(download_dir.path().join("video.mp4")).unwrap() == content);
+    assert_eq!(
+        fs::read(&outside_file).unwrap(),
+        b"Not part of the transfer"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn stale_transfers_are_removed() {
+    let download_dir = tempfile::tempdir().unwrap();
+    let transfer_directory = download_dir.path().join(".intershare");
+
+    let last_week = SystemTime::now() - Duration::from_secs(8 * 24 * 60 * 60);
+    let mut staged_files = vec![];
+
+    for (id, modified_at) in [("stale", last_week), ("recent", SystemTime::now())] {
+        let staged_file = transfer_directory
+            .join("staging")
+            .join(id)
+            .join("video.mp4");
+        fs::create_dir_all(staged_file.parent().unwrap()).unwrap();
+        fs::write(&staged_file, b"Partial").unwrap();
+
+        let journal = fs::File::create(transfer_directory.join(format!("{}.journal", id))).unwrap();
+        journal.set_modified(modified_at).unwrap();
+
+        staged_files.push(staged_file);
+    }
+
+    let _downloader = start_downloader(download_dir.path());
+
+    // Removed in the background
+    for _ in 0..100 {
+        if !staged_files[0].exists() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(!staged_files[0].exists());
+    assert!(!transfer_directory.join("stale.journal").exists());
+    assert!(staged_files[1].exists());
+    assert!(transfer_directory.join("recent.journal").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn cancelled_download_leaves_nothing_behind() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let album = shared_dir.path().join("album");
+    fs::create_dir_all(&album).unwrap();
+    fs::write(album.join("cover.txt"), b"Cover").unwrap();
+    fs::write(album.join("track.bin"), vec![7u8; 8 * 1024 * 1024]).unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![album.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let cancelled = tokio::task::spawn_blocking(move || {
+        request.set_progress_delegate(Box::new(CancelHalfway(Arc::downgrade(&request))));
+        request.accept()
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(cancelled, Err(ReceiveErrors::Cancelled)));
+    assert_eq!(fs::read_dir(download_dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn sender_can_cancel_a_download() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let shared_file = shared_dir.path().join("video.mp4");
+    fs::write(&shared_file, vec![7u8; 32 * 1024 * 1024]).unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let result = tokio::task::spawn_blocking(move || {
+        request.set_progress_delegate(Box::new(StopSharing(share_store)));
+        request.accept()
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        result,
+        Err(ReceiveErrors::CancelledBySender { reason: Some(reason) }) if reason == "Stopped sharing"
+    ));
+    assert_eq!(fs::read_dir(download_dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn shared_files_with_the_same_name_are_kept_apart() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    for folder in ["x", "y"] {
+        fs::create_dir(shared_dir.path().join(folder)).unwrap();
+        fs::write(shared_dir.path().join(folder).join("a.txt"), folder).unwrap();
+    }
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(
+            vec![
+                shared_dir
+                    .path()
+                    .join("x/a.txt")
+                    .to_string_lossy()
+                    .to_string(),
+                shared_dir
+                    .path()
+                    .join("y/a.txt")
+                    .to_string_lossy()
+                    .to_string(),
+            ],
+            true,
+            None,
+        )
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let paths: Vec<String> = request
+        .get_manifest()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    assert_eq!(paths, vec!["a.txt", "a (1).txt"]);
+
+    tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to receive files");
+
+    assert_eq!(fs::read(download_dir.path().join("a.txt")).unwrap(), b"x");
+    assert_eq!(
+        fs::read(download_dir.path().join("a (1).txt")).unwrap(),
+        b"y"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn data_without_files_is_shared() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let downloader = start_downloader(download_dir.path());
+
+    let share_store = sharer
+        .share_data(
+            vec![
+                SharedData {
+                    name: "export.csv".to_string(),
+                    data: b"a,b\n1,2".to_vec(),
+                },
+                SharedData {
+                    name: "export.csv".to_string(),
+                    data: b"c,d\n3,4".to_vec(),
+                },
+            ],
+            true,
+            None,
+        )
+        .await;
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let paths: Vec<String> = request
+        .get_manifest()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    assert_eq!(paths, vec!["export.csv", "export (1).csv"]);
+
+    let files = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to receive data");
+
+    assert_eq!(fs::read(&files[0]).unwrap(), b"a,b\n1,2");
+    assert_eq!(fs::read(&files[1]).unwrap(), b"c,d\n3,4");
+
+    let content: Vec<u8> = (0..256 * 1024).map(|index| (index % 251) as u8).collect();
+    let share_store = sharer
+        .share_stream(
+            "content.bin".to_string(),
+            content.len() as u64,
+            Box::new(MemoryReader(content.clone())),
+            true,
+            None,
+        )
+        .await;
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let files = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to receive stream");
+
+    assert!(fs::read(&files[0]).unwrap() == content);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn files_are_received_through_a_sink() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let album = shared_dir.path().join("album");
+    fs::create_dir_all(album.join("raw")).unwrap();
+    fs::write(album.join("cover.txt"), b"Cover").unwrap();
+    fs::write(album.join("raw").join("track.bin"), vec![7u8; 512 * 1024]).unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![album.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = MemorySink(received.clone());
+
+    let mut locations =
+        tokio::task::spawn_blocking(move || request.accept_with_sink(Box::new(sink)))
+            .await
+            .unwrap()
+            .expect("Failed to receive files");
+    locations.sort();
+
+    assert_eq!(
+        locations,
+        vec!["memory://album/cover.txt", "memory://album/raw/track.bin"]
+    );
+
+    let mut received = received.lock().unwrap().clone();
+    received.sort();
+
+    assert_eq!(
+        received[0],
+        ("album/cover.txt".to_string(), b"Cover".to_vec())
+    );
+    assert_eq!(
+        received[1],
+        ("album/raw/track.bin".to_string(), vec![7u8; 512 * 1024])
+    );
+
+    // Nothing was written to the storage directory
+    assert_eq!(fs::read_dir(download_dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn receive_policy_is_applied() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+
+    let shared_file = shared_dir.path().join("notes.txt");
+    fs::write(&shared_file, b"First").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], true, None)
+        .await;
+    let link = share_store.generate_link().unwrap();
+
+    let downloader = start_downloader(download_dir.path());
+
+    let destination_path = destination.path().to_string_lossy().to_string();
+    let receive = |collisions: CollisionPolicy| {
+        let request = tokio::runtime::Handle::current()
+            .block_on(downloader.request_download(link.clone(), None))
+            .expect("Download request failed");
+
+        request.set_receive_policy(ReceivePolicy {
+            layout: FolderLayout::PerSender,
+            collisions,
+        });
+
+        return request
+            .accept_to(destination_path.clone())
+            .expect("Failed to receive files");
+    };
+
+    let expected_path = destination.path().join("Sharer").join("notes.txt");
+
+    let files = tokio::task::block_in_place(|| receive(CollisionPolicy::Rename));
+    assert_eq!(files, vec![expected_path.to_string_lossy().to_string()]);
+
+    fs::write(&shared_file, b"Second").unwrap();
+
+    let files = tokio::task::block_in_place(|| receive(CollisionPolicy::Skip));
+    assert!(files.is_empty());
+    assert_eq!(fs::read(&expected_path).unwrap(), b"First");
+
+    let files = tokio::task::block_in_place(|| receive(CollisionPolicy::Overwrite));
+    assert_eq!(files, vec![expected_path.to_string_lossy().to_string()]);
+    assert_eq!(fs::read(&expected_path).unwrap(), b"Second");
+
+    let files = tokio::task::block_in_place(|| receive(CollisionPolicy::Rename));
+    assert!(files[0].ends_with("notes (1).txt"));
+
+    // Nothing was written to the storage directory
+    assert_eq!(fs::read_dir(download_dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn sender_folders_are_kept_apart_from_the_journal_directory() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let shared_file = shared_dir.path().join("notes.txt");
+    fs::write(&shared_file, b"Hidden").unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    tokio::task::block_in_place(|| sharer.change_device(device(".InterShare")));
+
+    let share_store = sharer
+        .share_files(vec![shared_file.to_string_lossy().to_string()], true, None)
+        .await;
+
+    let downloader = start_downloader(download_dir.path());
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let files = tokio::task::spawn_blocking(move || {
+        request.set_receive_policy(ReceivePolicy {
+            layout: FolderLayout::PerSender,
+            collisions: CollisionPolicy::Rename,
+        });
+        request.accept()
+    })
+    .await
+    .unwrap()
+    .expect("Failed to receive files");
+
+    let expected_path = download_dir.path().join("_.InterShare").join("notes.txt");
+    assert_eq!(files, vec![expected_path.to_string_lossy().to_string()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn receive_limits_are_enforced() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let downloader = start_downloader(download_dir.path());
+
+    let share_store = sharer
+        .share_data(
+            vec![
+                SharedData {
+                    name: "small.txt".to_string(),
+                    data: vec![1; 10],
+                },
+                SharedData {
+                    name: "large.bin".to_string(),
+                    data: vec![2; 4096],
+                },
+            ],
+            true,
+            None,
+        )
+        .await;
+    let link = share_store.generate_link().unwrap();
+
+    let receive = |limits: ReceiveLimits| {
+        let request = tokio::runtime::Handle::current()
+            .block_on(downloader.request_download(link.clone(), None))
+            .expect("Download request failed");
+
+        request.set_receive_limits(limits);
+        return request.accept();
+    };
+
+    let result = tokio::task::block_in_place(|| {
+        receive(ReceiveLimits {
+            max_transfer_size: Some(1024),
+            ..Default::default()
+        })
+    });
+    assert!(matches!(
+        result,
+        Err(ReceiveErrors::TransferTooLarge {
+            size: 4106,
+            limit: 1024
+        })
+    ));
+
+    let result = tokio::task::block_in_place(|| {
+        receive(ReceiveLimits {
+            max_file_size: Some(1024),
+            ..Default::default()
+        })
+    });
+    assert!(matches!(
+        result,
+        Err(ReceiveErrors::FileTooLarge { path, .. }) if path == "large.bin"
+    ));
+
+    let files = tokio::task::block_in_place(|| {
+        receive(ReceiveLimits {
+            max_transfer_size: Some(8192),
+            max_file_size: Some(4096),
+            space_check: SpaceCheck::Decline,
+        })
+    })
+    .expect("Failed to receive files");
+    assert_eq!(files.len(), 2);
+
+    // More than any test machine has available
+    let share_store = sharer
+        .share_stream(
+            "huge.bin".to_string(),
+            1 << 60,
+            Box::new(MemoryReader(vec![])),
+            true,
+            None,
+        )
+        .await;
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let result = tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap();
+    assert!(matches!(
+        result,
+        Err(ReceiveErrors::InsufficientSpace { required, .. }) if required == 1 << 60
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn rich_clipboard_is_streamed() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let items = vec![
+        ClipboardItem {
+            mime_type: "text/plain".to_string(),
+            data: b"Hello".to_vec(),
+        },
+        ClipboardItem {
+            mime_type: "text/html".to_string(),
+            data: b"<b>Hello</b>".to_vec(),
+        },
+        ClipboardItem {
+            mime_type: "image/png".to_string(),
+            data: (0..1024 * 1024).map(|index| (index % 251) as u8).collect(),
+        },
+    ];
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer.share_clipboard(items.clone(), true, None).await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    let intent = request.get_clipboard_intent().unwrap();
+    assert_eq!(intent.clipboard_content, "Hello");
+    assert_eq!(intent.representations.len(), 2);
+    assert_eq!(intent.representations[1].size, 1024 * 1024);
+
+    let received = tokio::task::spawn_blocking(move || request.accept_clipboard())
+        .await
+        .unwrap()
+        .expect("Failed to receive clipboard");
+
+    assert_eq!(received, items);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn large_clipboard_is_shared_as_files() {
+    let shared_dir = tempfile::tempdir().unwrap();
+    let download_dir = tempfile::tempdir().unwrap();
+
+    let image: Vec<u8> = (0..MAX_CLIPBOARD_SIZE + 1)
+        .map(|index| (index % 251) as u8)
+        .collect();
+    let items = vec![
+        ClipboardItem {
+            mime_type: "text/plain".to_string(),
+            data: b"Hello".to_vec(),
+        },
+        ClipboardItem {
+            mime_type: "image/png".to_string(),
+            data: image.clone(),
+        },
+    ];
+
+    let sharer = start_sharer(shared_dir.path()).await;
+    let share_store = sharer.share_clipboard(items, true, None).await;
+
+    let downloader = start_downloader(download_dir.path());
+
+    let request = downloader
+        .request_download(share_store.generate_link().unwrap(), None)
+        .await
+        .expect("Download request failed");
+
+    assert!(request.get_clipboard_intent().is_none());
+    assert_eq!(request.get_file_transfer_intent().unwrap().file_count, 2);
+
+    tokio::task::spawn_blocking(move || request.accept())
+        .await
+        .unwrap()
+        .expect("Failed to receive files");
+
+    assert_eq!(
+        fs::read(download_dir.path().join("clipboard.txt")).unwrap(),
+        b"Hello"
+    );
+    assert_eq!(
+        fs::read(download_dir.path().join("clipboard.png")).unwrap(),
+        image
+    );
+}